@@ -1,18 +1,150 @@
-
 struct Cell {
-    particle_type: u32
+    particle_type: u32,
+    variant: u32,
+    flags: u32,
+    lifetime: u32,
+    temperature: f32,
+    // How many steps of heat shock a brittle cell has taken
+    damage: u32,
+}
+
+struct Material {
+    colour: vec4<f32>,
+    density: f32,
+    state: u32,
+    flammability: f32,
+    dispersion: u32,
+    lifetime: u32,
+    decays_into: u32,
+    burns_into: u32,
+    burns_as: u32,
+    ignites: u32,
+    ignition_limit: f32,
+    flicker: f32,
+    temperature: f32,
+    conductivity: f32,
+    heat_limit: f32,
+    heats_into: u32,
+    cold_limit: f32,
+    cools_into: u32,
+    shatter_limit: f32,
+    shatters_into: u32,
+    electricity: u32,
+    integrity: u32,
 }
 
 struct PushConstants {
     drawing_start: vec2<f32>,
     drawing_end: vec2<f32>,
     brush_radius: f32,
+    material: u32,
+    brush_shape: u32,
+    spray_density: f32,
+    seed: u32,
+    frame: u32,
+    brush_mode: u32,
+    replace_material: u32,
+}
+
+// The material that fills empty space, see assets/default.materials.ron
+const AIR = 0u;
+
+// Bits of Cell::flags
+// Set once the cell has taken on its material's starting temperature
+const CELL_INITIALISED = 1u;
+// Carrying a pulse of electricity this step
+const CELL_CHARGED = 2u;
+// Spent after carrying a pulse, and can't be charged again until the next step
+const CELL_DISCHARGED = 4u;
+
+// Bits of Material::electricity
+const CONDUCTS_ELECTRICITY = 1u;
+// Always charged, a source of pulses for any conductor it touches
+const POWERED = 2u;
+
+// Values of Material::state
+const SOLID = 0u;
+const POWDER = 1u;
+const LIQUID = 2u;
+const GAS = 3u;
+
+// Values of the brush shape, matching `BrushShape`
+const BRUSH_CIRCLE = 0u;
+const BRUSH_SQUARE = 1u;
+const BRUSH_SPRAY = 2u;
+const BRUSH_LINE = 3u;
+const BRUSH_RECTANGLE = 4u;
+// Flood fills are done by `flood_fill.wgsl` rather than the brush
+const BRUSH_BUCKET = 5u;
+
+// Values of the brush mode, matching `BrushMode`
+const BRUSH_MODE_OVERWRITE = 0u;
+const BRUSH_MODE_FILL_EMPTY = 1u;
+const BRUSH_MODE_REPLACE = 2u;
+
+fn new_cell(particle_type: u32) -> Cell {
+    return Cell(particle_type, 0u, 0u, 0u, 0.0, 0u);
 }
 
-const AIR_COLOUR = vec4<f32>(0.02, 0.02, 0.02, 1.0);
-const SAND_COLOUR = vec4<f32>(0.7, 0.58, 0.44, 1.0);
-const STONE_COLOUR = vec4<f32>(0.4, 0.4, 0.4, 1.0);
-const EPSILON = 0.01;
+// The shortest distance from `point` to the line segment between `start` and `end`
+fn distance_to_segment(point: vec2<f32>, start: vec2<f32>, end: vec2<f32>) -> f32 {
+    let segment = end - start;
+    let length_squared = dot(segment, segment);
+    var along = 0.0;
+    if (length_squared > 0.0) {
+        along = clamp(dot(point - start, segment) / length_squared, 0.0, 1.0);
+    }
+    return length(point - (start + segment * along));
+}
+
+// Whether sweeping a square with half width `radius` from `start` to `end` covers `point`.
+// Each axis limits how far along the segment the square can be while still covering
+// the point, and it is covered if those ranges overlap.
+fn in_swept_square(point: vec2<f32>, start: vec2<f32>, end: vec2<f32>, radius: f32) -> bool {
+    let segment = end - start;
+    let offset = point - start;
+    var along_min = 0.0;
+    var along_max = 1.0;
+    for (var axis = 0; axis < 2; axis++) {
+        if (abs(segment[axis]) < 0.0001) {
+            if (abs(offset[axis]) > radius) {
+                return false;
+            }
+        } else {
+            let a = (offset[axis] - radius) / segment[axis];
+            let b = (offset[axis] + radius) / segment[axis];
+            along_min = max(along_min, min(a, b));
+            along_max = min(along_max, max(a, b));
+        }
+    }
+    return along_min <= along_max;
+}
+
+// How far `point` is from the nearest edge of the rectangle with the given corners,
+// measured along whichever axis is furthest
+fn distance_to_rectangle_edge(point: vec2<f32>, corner_a: vec2<f32>, corner_b: vec2<f32>) -> f32 {
+    let low = min(corner_a, corner_b);
+    let high = max(corner_a, corner_b);
+    let outside = max(max(low - point, point - high), vec2<f32>(0.0, 0.0));
+    if (outside.x > 0.0 || outside.y > 0.0) {
+        return max(outside.x, outside.y);
+    }
+    let inside = min(point - low, high - point);
+    return min(inside.x, inside.y);
+}
+
+// Whether a brush stroke from `start` to `end` covers `point`. The spray brush covers the
+// same cells as the circle, the drawing shader then skips some of them.
+fn brush_covers(point: vec2<f32>, start: vec2<f32>, end: vec2<f32>, radius: f32, shape: u32) -> bool {
+    if (shape == BRUSH_SQUARE) {
+        return in_swept_square(point, start, end, radius);
+    }
+    if (shape == BRUSH_RECTANGLE) {
+        // Walls `radius` thick either side of the rectangle's outline
+        return distance_to_rectangle_edge(point, start, end) <= radius;
+    }
+    return round(distance_to_segment(point, start, end)) <= radius;
+}
 
 fn hash(value: u32) -> u32 {
     var state = value;
@@ -4,33 +4,54 @@ var<push_constant> drawing_constants: PushConstants;
 @group(0) @binding(0)
 var<uniform> simulation_size: vec2<u32>;
 @group(0) @binding(1)
-var texture: texture_storage_2d<rgba8unorm, read_write>;
+var<storage, read_write> cells: array<Cell>;
 
 @compute @workgroup_size(8, 8, 1)
 fn draw(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let pixel = vec2<u32>(invocation_id.xy);
 
-    if (pixel.x >= simulation_size.x && pixel.y >= simulation_size.y) {
+    if (pixel.x >= simulation_size.x || pixel.y >= simulation_size.y) {
         return;
     }
 
     if (drawing_constants.brush_radius > 0.0) {
         let current_pixel = vec2<f32>(pixel);
-        let drawing_position = drawing_constants.drawing_end;
-        draw_circle(current_pixel, drawing_position, drawing_constants.brush_radius);
+        draw_stroke(current_pixel, drawing_constants.drawing_start, drawing_constants.drawing_end, drawing_constants.brush_radius);
     }
 }
 
-fn draw_circle(current_pixel: vec2<f32>, centre: vec2<f32>, radius: f32) {
-    let y_min = centre.y - radius;
-    let y_max = centre.y + radius;
-    let x_min = centre.x - radius;
-    let x_max = centre.x + radius;
+// Sweeps the brush from the start of the stroke to the end, which for freehand brushes is
+// from where the cursor was last frame to where it is now so fast strokes don't leave gaps
+fn draw_stroke(current_pixel: vec2<f32>, start: vec2<f32>, end: vec2<f32>, radius: f32) {
+    let x_min = min(start.x, end.x) - radius;
+    let x_max = max(start.x, end.x) + radius;
+    let y_min = min(start.y, end.y) - radius;
+    let y_max = max(start.y, end.y) + radius;
 
-    if (current_pixel.x >= x_min && current_pixel.x <= x_max && current_pixel.y >= y_min && current_pixel.y <= y_max) {
-        let distance = length(current_pixel - centre);
-        if (round(distance) <= radius) {
-            textureStore(texture, vec2<i32>(current_pixel), SAND_COLOUR);
+    if (current_pixel.x < x_min || current_pixel.x > x_max || current_pixel.y < y_min || current_pixel.y > y_max) {
+        return;
+    }
+    if (!brush_covers(current_pixel, start, end, radius, drawing_constants.brush_shape)) {
+        return;
+    }
+
+    let location = vec2<i32>(current_pixel);
+    if (drawing_constants.brush_shape == BRUSH_SPRAY) {
+        let frame_seed = hash(drawing_constants.seed ^ hash(drawing_constants.frame));
+        let rand = randomFloat(frame_seed ^ hash(u32(location.x) ^ hash(u32(location.y))));
+        if (rand >= drawing_constants.spray_density) {
+            return;
         }
     }
+
+    let index = location.y * i32(simulation_size.x) + location.x;
+    let existing = cells[index].particle_type;
+    if (drawing_constants.brush_mode == BRUSH_MODE_FILL_EMPTY && existing != AIR) {
+        return;
+    }
+    if (drawing_constants.brush_mode == BRUSH_MODE_REPLACE && existing != drawing_constants.replace_material) {
+        return;
+    }
+
+    cells[index] = new_cell(drawing_constants.material);
 }
@@ -1,51 +1,498 @@
 #import "shaders/core.wgsl"
 
+struct SimulationConstants {
+    frame: u32,
+    pass_index: u32,
+    seed: u32,
+    // The material past the edges of the simulation
+    wall: u32,
+}
+
+// The brush outline drawn over the simulation by `colourise`, or the line or rectangle
+// being dragged out when `preview` is set
+struct CursorConstants {
+    start: vec2<f32>,
+    end: vec2<f32>,
+    radius: f32,
+    shape: u32,
+    material: u32,
+    preview: u32,
+}
+
+// Two neighbouring cells of `first` and `second` turning into the products with the chance `probability`
+struct Reaction {
+    first: u32,
+    second: u32,
+    first_product: u32,
+    second_product: u32,
+    probability: f32,
+}
+
+struct ReactionTable {
+    count: u32,
+    reactions: array<Reaction>,
+}
+
+var<push_constant> simulation_constants: SimulationConstants;
+var<push_constant> cursor: CursorConstants;
 @group(0) @binding(0)
 var<uniform> size: vec2<u32>;
 @group(0) @binding(1)
-var texture: texture_storage_2d<rgba8unorm, read_write>;
+var texture: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(2)
+var<storage, read> cells: array<Cell>;
+@group(0) @binding(3)
+var<storage, read> materials: array<Material>;
+@group(0) @binding(4)
+var<storage, read_write> next_cells: array<Cell>;
+@group(0) @binding(5)
+var<storage, read> reaction_table: ReactionTable;
+
+fn in_bounds(location: vec2<i32>) -> bool {
+    return location.x >= 0 && location.y >= 0 && location.x < i32(size.x) && location.y < i32(size.y);
+}
+
+fn cell_index(location: vec2<i32>) -> i32 {
+    return location.y * i32(size.x) + location.x;
+}
+
+fn get_cell(location: vec2<i32>) -> Cell {
+    // Treat everything outside the simulation as a wall
+    if (!in_bounds(location)) {
+        return new_cell(simulation_constants.wall);
+    }
+    return cells[cell_index(location)];
+}
+
+fn set_cell(location: vec2<i32>, cell: Cell) {
+    if (in_bounds(location)) {
+        next_cells[cell_index(location)] = cell;
+    }
+}
 
 @compute @workgroup_size(8, 8, 1)
 fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
     let location = vec2<i32>(invocation_id.xy);
-    var colour = AIR_COLOUR;
+    if (!in_bounds(location)) {
+        return;
+    }
+
+    var particle_type = AIR;
 
-    // Add a stone barrier at the bottom of the screen
-    if(location.y > 640) {
-        colour = STONE_COLOUR;
+    // Add a floor of the wall material along the bottom ninth of the screen
+    if(location.y > i32(size.y * 8u / 9u)) {
+        particle_type = simulation_constants.wall;
     }
 
-    textureStore(texture, location, colour);
+    set_cell(location, new_cell(particle_type));
+}
+
+// Whether `cell` can push `other` out of the way when moving in `direction`.
+// Heavier cells sink through lighter ones and lighter cells rise through heavier ones,
+// but nothing moves through a solid.
+fn can_displace(cell: Cell, other: Cell, direction: vec2<i32>) -> bool {
+    let other_material = materials[other.particle_type];
+    if (other_material.state == SOLID) {
+        return false;
+    }
+
+    let density = materials[cell.particle_type].density;
+    if (direction.y < 0) {
+        return density < other_material.density;
+    }
+    return density > other_material.density;
+}
+
+// Whether the upper cell of a vertical or diagonal pair should trade places with the
+// lower one, either because it falls into it or because the lower one rises into it
+fn should_swap_vertically(upper: Cell, lower: Cell) -> bool {
+    let upper_state = materials[upper.particle_type].state;
+    let lower_state = materials[lower.particle_type].state;
+
+    let upper_falls = (upper_state == POWDER || upper_state == LIQUID)
+        && can_displace(upper, lower, vec2<i32>(0, 1));
+    let lower_rises = lower_state == GAS && lower.particle_type != AIR
+        && can_displace(lower, upper, vec2<i32>(0, -1));
+
+    return upper_falls || lower_rises;
+}
+
+// A random number that differs between cells and frames but is the same every run with the same seed.
+// Different values of `salt` give independent numbers for the same cell.
+fn random_at(location: vec2<i32>, salt: u32) -> f32 {
+    let frame_seed = hash(simulation_constants.seed ^ hash(simulation_constants.frame));
+    return randomFloat(hash(frame_seed ^ hash(u32(location.x) ^ hash(u32(location.y) ^ salt))));
+}
+
+// How long a new cell of `material` lasts, varied so that cells made together don't all vanish together
+fn start_lifetime(material: Material, rand: f32) -> u32 {
+    return max(1u, u32(f32(material.lifetime) * (0.5 + rand)));
+}
+
+// Whether `cell` sets light to flammable cells next to it, which sparks from charged
+// conductors do as well as fire
+fn is_burning(cell: Cell) -> bool {
+    return materials[cell.particle_type].ignites != 0u || (cell.flags & CELL_CHARGED) != 0u;
+}
+
+// Sets light to a cell, turning it into whatever it burns as, which leaves behind whatever
+// it burns into
+fn ignite(cell: Cell, rand: f32) -> Cell {
+    let material = materials[cell.particle_type];
+    var fire = new_cell(material.burns_as);
+    fire.variant = material.burns_into;
+    fire.lifetime = start_lifetime(materials[material.burns_as], rand);
+    return fire;
+}
+
+// Flammable cells sharing a block with fire catch light with their material's flammability
+fn burn(cell: Cell, fire_in_block: bool, rand: f32) -> Cell {
+    let material = materials[cell.particle_type];
+    if (!fire_in_block || rand >= material.flammability) {
+        return cell;
+    }
+    return ignite(cell, rand / material.flammability);
+}
+
+struct ReactionResult {
+    first: Cell,
+    second: Cell,
+    reacted: bool,
+}
+
+// Looks up the reaction between two neighbouring cells, whichever way round it is in the table,
+// and turns them into its products if the reaction's chance comes up. The products keep the
+// temperatures of the cells they replace.
+fn react(first: Cell, second: Cell, rand: f32) -> ReactionResult {
+    for (var i = 0u; i < reaction_table.count; i++) {
+        let reaction = reaction_table.reactions[i];
+        if (reaction.first == first.particle_type && reaction.second == second.particle_type) {
+            if (rand < reaction.probability) {
+                return ReactionResult(change_material(first, reaction.first_product), change_material(second, reaction.second_product), true);
+            }
+            break;
+        }
+        if (reaction.first == second.particle_type && reaction.second == first.particle_type) {
+            if (rand < reaction.probability) {
+                return ReactionResult(change_material(first, reaction.second_product), change_material(second, reaction.first_product), true);
+            }
+            break;
+        }
+    }
+    return ReactionResult(first, second, false);
+}
+
+// Cells made by drawing, burning or decaying start out at their material's temperature
+fn initialise(cell: Cell) -> Cell {
+    if ((cell.flags & CELL_INITIALISED) != 0u) {
+        return cell;
+    }
+
+    var initialised = cell;
+    initialised.temperature = materials[cell.particle_type].temperature;
+    initialised.flags = cell.flags | CELL_INITIALISED;
+    return initialised;
+}
+
+// Counts down the lifetime of cells whose material doesn't last forever, and turns them into
+// their `variant` when it runs out. A lifetime of 0 means the countdown hasn't started yet,
+// as with cells that have just been drawn.
+fn age(cell: Cell, rand: f32) -> Cell {
+    let material = materials[cell.particle_type];
+    if (material.lifetime == 0u) {
+        return cell;
+    }
+
+    var aged = cell;
+    if (cell.lifetime == 0u) {
+        aged.variant = material.decays_into;
+        aged.lifetime = start_lifetime(material, rand);
+    } else if (cell.lifetime == 1u) {
+        aged = new_cell(cell.variant);
+    } else {
+        aged.lifetime = cell.lifetime - 1u;
+    }
+    return aged;
+}
+
+// Whether `cell` is a liquid that still flows sideways in this pass
+fn flows(cell: Cell) -> bool {
+    let material = materials[cell.particle_type];
+    return material.state == LIQUID && simulation_constants.pass_index < material.dispersion;
+}
+
+// Whether `cell` is a gas that still drifts sideways in this pass, which it only does
+// through other gases so that it billows out under ceilings but never into walls or water
+fn drifts(cell: Cell, other: Cell) -> bool {
+    let material = materials[cell.particle_type];
+    return material.state == GAS && cell.particle_type != AIR && cell.particle_type != other.particle_type
+        && materials[other.particle_type].state == GAS
+        && simulation_constants.pass_index < material.dispersion;
+}
+
+// Whether the two cells of a horizontal pair should trade places, because a liquid flows
+// or a gas drifts from one into the other
+fn should_swap_sideways(left: Cell, right: Cell, left_location: vec2<i32>) -> bool {
+    let left_flows = flows(left) && can_displace(left, right, vec2<i32>(1, 0));
+    let right_flows = flows(right) && can_displace(right, left, vec2<i32>(-1, 0));
+    let drift = (drifts(left, right) || drifts(right, left))
+        && random_at(left_location, 5u + simulation_constants.pass_index) < 0.5;
+    return left_flows || right_flows || drift;
 }
 
+// Each invocation owns one 2x2 block of cells and writes every cell of it to the next
+// buffer, so no two invocations ever touch the same cell. The blocks shift by one cell
+// every frame which lets particles cross block boundaries.
+// Liquids and gases that spread further than one cell sideways in a step get extra
+// passes after the first, which only move cells along the rows of each block. Those
+// shift sideways by one cell every pass.
 @compute @workgroup_size(8, 8, 1)
 fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let location = vec2<i32>(invocation_id.xy);
-    let current_particle_colour = textureLoad(texture, location);
-
-    if (distance(current_particle_colour, AIR_COLOUR) < EPSILON) {
-        // do nothing
-    } else if (distance(current_particle_colour, SAND_COLOUR) < EPSILON) {
-        // check below and fall if we can
-        let colour_below = textureLoad(texture, location + vec2<i32>(0, 1));
-        if (distance(colour_below, AIR_COLOUR) < EPSILON) {
-            // fall straight down
-            textureStore(texture, location, AIR_COLOUR);
-            textureStore(texture, location + vec2<i32>(0, 1), SAND_COLOUR);
-        } else {
-            // there is something directly below
-            // select a random direction diagonally down
-            // and try to fall there
-            let rand = randomFloat(invocation_id.x + invocation_id.y);
-            var new_x = -1;
-            if (rand > 0.5) {
-                new_x = 1;
+    let offset = i32(simulation_constants.frame % 2u);
+    let horizontal_offset = i32((simulation_constants.frame + simulation_constants.pass_index) % 2u);
+    let origin = vec2<i32>(invocation_id.xy) * 2 - vec2<i32>(horizontal_offset, offset);
+    if (origin.x >= i32(size.x) || origin.y >= i32(size.y)) {
+        return;
+    }
+
+    let top_left_location = origin;
+    let top_right_location = origin + vec2<i32>(1, 0);
+    let bottom_left_location = origin + vec2<i32>(0, 1);
+    let bottom_right_location = origin + vec2<i32>(1, 1);
+
+    var top_left = get_cell(top_left_location);
+    var top_right = get_cell(top_right_location);
+    var bottom_left = get_cell(bottom_left_location);
+    var bottom_right = get_cell(bottom_right_location);
+
+    // Cells past the edge of the grid read as walls, which mustn't burn or react
+    let top_left_in_bounds = in_bounds(top_left_location);
+    let top_right_in_bounds = in_bounds(top_right_location);
+    let bottom_left_in_bounds = in_bounds(bottom_left_location);
+    let bottom_right_in_bounds = in_bounds(bottom_right_location);
+
+    // Everything but spreading sideways happens in the first pass
+    if (simulation_constants.pass_index == 0u) {
+        top_left = initialise(top_left);
+        top_right = initialise(top_right);
+        bottom_left = initialise(bottom_left);
+        bottom_right = initialise(bottom_right);
+
+        // burn and age before anything moves
+        let fire_in_block = is_burning(top_left) || is_burning(top_right)
+            || is_burning(bottom_left) || is_burning(bottom_right);
+        if (top_left_in_bounds) {
+            top_left = age(burn(top_left, fire_in_block, random_at(top_left_location, 1u)), random_at(top_left_location, 2u));
+        }
+        if (top_right_in_bounds) {
+            top_right = age(burn(top_right, fire_in_block, random_at(top_right_location, 1u)), random_at(top_right_location, 2u));
+        }
+        if (bottom_left_in_bounds) {
+            bottom_left = age(burn(bottom_left, fire_in_block, random_at(bottom_left_location, 1u)), random_at(bottom_left_location, 2u));
+        }
+        if (bottom_right_in_bounds) {
+            bottom_right = age(burn(bottom_right, fire_in_block, random_at(bottom_right_location, 1u)), random_at(bottom_right_location, 2u));
+        }
+
+        // react along the rows and then down the columns, each cell at most once per step
+        var row_reacted = false;
+        if (top_left_in_bounds && top_right_in_bounds) {
+            let top_row = react(top_left, top_right, random_at(top_left_location, 3u));
+            top_left = top_row.first;
+            top_right = top_row.second;
+            row_reacted = top_row.reacted;
+        }
+        if (bottom_left_in_bounds && bottom_right_in_bounds) {
+            let bottom_row = react(bottom_left, bottom_right, random_at(bottom_left_location, 3u));
+            bottom_left = bottom_row.first;
+            bottom_right = bottom_row.second;
+            row_reacted = row_reacted || bottom_row.reacted;
+        }
+        if (!row_reacted) {
+            if (top_left_in_bounds && bottom_left_in_bounds) {
+                let left_column = react(top_left, bottom_left, random_at(top_left_location, 4u));
+                top_left = left_column.first;
+                bottom_left = left_column.second;
             }
-            let colour_below_diagonally = textureLoad(texture, location + vec2<i32>(new_x, 1));
-            if (distance(colour_below_diagonally, AIR_COLOUR) < EPSILON) {
-                textureStore(texture, location, AIR_COLOUR);
-                textureStore(texture, location + vec2<i32>(new_x, 1), SAND_COLOUR);
+            if (top_right_in_bounds && bottom_right_in_bounds) {
+                let right_column = react(top_right, bottom_right, random_at(top_right_location, 4u));
+                top_right = right_column.first;
+                bottom_right = right_column.second;
             }
         }
+
+        // fall or rise straight down each column
+        let left_column_moves = should_swap_vertically(top_left, bottom_left);
+        if (left_column_moves) {
+            let cell = top_left;
+            top_left = bottom_left;
+            bottom_left = cell;
+        }
+        let right_column_moves = should_swap_vertically(top_right, bottom_right);
+        if (right_column_moves) {
+            let cell = top_right;
+            top_right = bottom_right;
+            bottom_right = cell;
+        }
+
+        // if neither column could move then something is in the way
+        // so try to topple diagonally instead
+        if (!left_column_moves && !right_column_moves) {
+            let rand = random_at(origin, 0u);
+            if (rand > 0.5) {
+                if (should_swap_vertically(top_left, bottom_right)) {
+                    let cell = top_left;
+                    top_left = bottom_right;
+                    bottom_right = cell;
+                } else if (should_swap_vertically(top_right, bottom_left)) {
+                    let cell = top_right;
+                    top_right = bottom_left;
+                    bottom_left = cell;
+                }
+            } else {
+                if (should_swap_vertically(top_right, bottom_left)) {
+                    let cell = top_right;
+                    top_right = bottom_left;
+                    bottom_left = cell;
+                } else if (should_swap_vertically(top_left, bottom_right)) {
+                    let cell = top_left;
+                    top_left = bottom_right;
+                    bottom_right = cell;
+                }
+            }
+        }
+    }
+
+    // spread sideways along each row
+    if (should_swap_sideways(top_left, top_right, top_left_location)) {
+        let cell = top_left;
+        top_left = top_right;
+        top_right = cell;
+    }
+    if (should_swap_sideways(bottom_left, bottom_right, bottom_left_location)) {
+        let cell = bottom_left;
+        bottom_left = bottom_right;
+        bottom_right = cell;
+    }
+
+    set_cell(top_left_location, top_left);
+    set_cell(top_right_location, top_right);
+    set_cell(bottom_left_location, bottom_left);
+    set_cell(bottom_right_location, bottom_right);
+}
+
+// Turns a cell into another material, keeping its temperature but no hotter or colder than
+// the new material can stand, so that it doesn't change again straight away
+fn change_material(cell: Cell, particle_type: u32) -> Cell {
+    let material = materials[particle_type];
+    var changed = new_cell(particle_type);
+    changed.flags = cell.flags & CELL_INITIALISED;
+    changed.temperature = clamp(cell.temperature, material.cold_limit, material.heat_limit);
+    return changed;
+}
+
+// Whether `cell` is charged or powered, so that conductors next to it pick up the charge
+fn is_charged(cell: Cell) -> bool {
+    return (cell.flags & CELL_CHARGED) != 0u || (materials[cell.particle_type].electricity & POWERED) != 0u;
+}
+
+// Moves pulses of electricity along conductors one cell per step. A charged cell is spent
+// for the step after, so pulses only ever travel away from where they came from.
+fn conduct(cell: Cell, charged_neighbour: bool) -> u32 {
+    let flags = cell.flags & ~(CELL_CHARGED | CELL_DISCHARGED);
+    if ((materials[cell.particle_type].electricity & CONDUCTS_ELECTRICITY) == 0u) {
+        return flags;
+    }
+    if ((cell.flags & CELL_CHARGED) != 0u) {
+        return flags | CELL_DISCHARGED;
+    }
+    if ((cell.flags & CELL_DISCHARGED) == 0u && charged_neighbour) {
+        return flags | CELL_CHARGED;
+    }
+    return flags;
+}
+
+// Heat flows between each cell and its four neighbours, held back by whichever of the two
+// conducts worse. Cells that end up hotter or colder than their material can stand melt,
+// boil or freeze into another material. Brittle ones are damaged by each step in which their
+// temperature changes too quickly and crack once that is more than their integrity.
+// Electricity moves between neighbours here too.
+@compute @workgroup_size(8, 8, 1)
+fn heat(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    if (!in_bounds(location)) {
+        return;
+    }
+
+    var cell = initialise(get_cell(location));
+    let material = materials[cell.particle_type];
+
+    var neighbours = array<vec2<i32>, 4>(vec2<i32>(1, 0), vec2<i32>(-1, 0), vec2<i32>(0, 1), vec2<i32>(0, -1));
+    var temperature = cell.temperature;
+    var charged_neighbour = false;
+    for (var i = 0; i < 4; i++) {
+        let neighbour_location = location + neighbours[i];
+        if (in_bounds(neighbour_location)) {
+            let neighbour = initialise(get_cell(neighbour_location));
+            let conductivity = min(material.conductivity, materials[neighbour.particle_type].conductivity);
+            temperature += 0.25 * conductivity * (neighbour.temperature - cell.temperature);
+            charged_neighbour = charged_neighbour || is_charged(neighbour);
+        }
+    }
+    if (abs(temperature - cell.temperature) > material.shatter_limit) {
+        cell.damage += 1u;
+    }
+    cell.temperature = temperature;
+    cell.flags = conduct(cell, charged_neighbour);
+
+    if (cell.damage > material.integrity) {
+        cell = change_material(cell, material.shatters_into);
+    } else if (temperature > material.ignition_limit) {
+        cell = ignite(cell, random_at(location, 6u));
+    } else if (temperature > material.heat_limit) {
+        cell = change_material(cell, material.heats_into);
+    } else if (temperature < material.cold_limit) {
+        cell = change_material(cell, material.cools_into);
+    }
+
+    set_cell(location, cell);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn colourise(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    if (!in_bounds(location)) {
+        return;
+    }
+
+    // `get_cell` needs the simulation constants for cells past the edge, which this pass doesn't have
+    let cell = cells[cell_index(location)];
+    let material = materials[cell.particle_type];
+    // See-through materials only tint the empty space they fill
+    var colour = vec4<f32>(mix(materials[AIR].colour.rgb, material.colour.rgb, material.colour.a), 1.0);
+
+    // Flames change brightness every step
+    if (material.flicker > 0.0) {
+        let flicker = randomFloat(hash(cell.lifetime) ^ hash(u32(location.x) ^ hash(u32(location.y))));
+        colour = vec4<f32>(colour.rgb * (1.0 - material.flicker * flicker), colour.a);
+    }
+
+    // Pulses of electricity light up the conductors they travel along
+    if ((cell.flags & CELL_CHARGED) != 0u) {
+        colour = vec4<f32>(mix(colour.rgb, vec3<f32>(1.0, 0.95, 0.6), 0.7), colour.a);
+    }
+
+    let point = vec2<f32>(location);
+    let covered = brush_covers(point, cursor.start, cursor.end, cursor.radius, cursor.shape);
+    if (cursor.preview != 0u) {
+        // Show what releasing the mouse will draw
+        if (covered) {
+            colour = vec4<f32>(mix(colour.rgb, materials[cursor.material].colour.rgb, 0.7), 1.0);
+        }
+    } else if (covered && !brush_covers(point, cursor.start, cursor.end, cursor.radius - 1.0, cursor.shape)) {
+        // Outline the cells the brush would cover
+        colour = vec4<f32>(mix(colour.rgb, vec3<f32>(1.0, 1.0, 1.0), 0.6), 1.0);
     }
+
+    textureStore(texture, location, colour);
 }
@@ -0,0 +1,110 @@
+#import "shaders/core.wgsl"
+
+struct FloodFillConstants {
+    start: vec2<i32>,
+    material: u32,
+}
+
+// What the fill is replacing, and whether a propagate pass has marked anything since the
+// flag was last cleared
+struct FloodFillStatus {
+    target_type: u32,
+    changed: atomic<u32>,
+}
+
+var<push_constant> fill_constants: FloodFillConstants;
+@group(0) @binding(0)
+var<uniform> size: vec2<u32>;
+@group(0) @binding(1)
+var<storage, read_write> cells: array<Cell>;
+@group(0) @binding(2)
+var<storage, read_write> marks: array<atomic<u32>>;
+@group(0) @binding(3)
+var<storage, read_write> status: FloodFillStatus;
+
+var<workgroup> tile_changed: atomic<u32>;
+
+fn in_bounds(location: vec2<i32>) -> bool {
+    return location.x >= 0 && location.y >= 0 && location.x < i32(size.x) && location.y < i32(size.y);
+}
+
+fn cell_index(location: vec2<i32>) -> i32 {
+    return location.y * i32(size.x) + location.x;
+}
+
+fn is_marked(location: vec2<i32>) -> bool {
+    return in_bounds(location) && atomicLoad(&marks[cell_index(location)]) != 0u;
+}
+
+// Clears the marks left by the last fill and marks the clicked cell, remembering its
+// material as the one to fill over since the simulation may move it later
+@compute @workgroup_size(8, 8, 1)
+fn seed(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    if (!in_bounds(location)) {
+        return;
+    }
+
+    var is_start = false;
+    if (all(location == fill_constants.start)) {
+        let target_type = cells[cell_index(location)].particle_type;
+        status.target_type = target_type;
+        // Filling something with itself would change nothing
+        is_start = target_type != fill_constants.material;
+    }
+    atomicStore(&marks[cell_index(location)], u32(is_start));
+}
+
+// Marks cells of the clicked material that are next to a marked cell. Each workgroup keeps
+// going until nothing in its tile changes, so the fill can cross a whole tile in one pass
+// rather than a single cell. Marking anything raises the changed flag, so the passes go on
+// until one batch of them leaves it clear.
+@compute @workgroup_size(8, 8, 1)
+fn propagate(
+    @builtin(global_invocation_id) invocation_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let location = vec2<i32>(invocation_id.xy);
+    let fillable = in_bounds(location) && cells[cell_index(location)].particle_type == status.target_type;
+
+    loop {
+        if (local_index == 0u) {
+            atomicStore(&tile_changed, 0u);
+        }
+        workgroupBarrier();
+
+        if (fillable && !is_marked(location)) {
+            let next_to_fill = is_marked(location + vec2<i32>(1, 0))
+                || is_marked(location - vec2<i32>(1, 0))
+                || is_marked(location + vec2<i32>(0, 1))
+                || is_marked(location - vec2<i32>(0, 1));
+            if (next_to_fill) {
+                atomicStore(&marks[cell_index(location)], 1u);
+                atomicStore(&tile_changed, 1u);
+                atomicStore(&status.changed, 1u);
+            }
+        }
+        storageBarrier();
+        workgroupBarrier();
+
+        // Every invocation in the workgroup sees the same value here, so they all stop together
+        if (atomicLoad(&tile_changed) == 0u) {
+            break;
+        }
+        workgroupBarrier();
+    }
+}
+
+// Replaces every marked cell that still holds the material being filled over
+@compute @workgroup_size(8, 8, 1)
+fn apply(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = vec2<i32>(invocation_id.xy);
+    if (!in_bounds(location)) {
+        return;
+    }
+
+    let index = cell_index(location);
+    if (atomicLoad(&marks[index]) != 0u && cells[index].particle_type == status.target_type) {
+        cells[index] = new_cell(fill_constants.material);
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::render::render_resource::{Buffer, BufferInitDescriptor, BufferUsages};
+use bevy::render::render_resource::{Buffer, BufferDescriptor, BufferInitDescriptor, BufferUsages};
 use bevy::render::renderer::RenderDevice;
 
 pub fn create_uniform_buffer<T: bytemuck::Pod + bytemuck::Zeroable>(
@@ -12,3 +12,16 @@ pub fn create_uniform_buffer<T: bytemuck::Pod + bytemuck::Zeroable>(
         usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
     })
 }
+
+pub fn create_storage_buffer(
+    device: &RenderDevice,
+    size: u64,
+    label: Option<&str>,
+) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label,
+        size,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
@@ -41,7 +41,14 @@ fn camera_controller(
             transform.translation.z = z;
         }
 
+        // Ctrl+scroll changes the brush size instead of zooming
+        let resizing_brush = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+
         for MouseWheel { x, y, unit} in mouse_wheel_events.iter() {
+            if resizing_brush {
+                continue;
+            }
+
             let mut x_scroll = 0.0;
             let mut y_scroll = 0.0;
 
@@ -0,0 +1,33 @@
+use bevy::render::render_resource::Buffer;
+use bevy::render::renderer::RenderDevice;
+use crate::buffer;
+
+/// The state of a single simulation cell, laid out to match `Cell` in `core.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Cell {
+    pub particle_type: u32,
+    pub variant: u32,
+    pub flags: u32,
+    pub lifetime: u32,
+    pub temperature: f32,
+    pub damage: u32,
+}
+
+pub fn cell_buffer_size(width: u32, height: u32) -> u64 {
+    width as u64 * height as u64 * std::mem::size_of::<Cell>() as u64
+}
+
+/// Creates the pair of buffers the simulation ping-pongs between, filled with air.
+pub fn create_cell_buffers(device: &RenderDevice, width: u32, height: u32) -> [Buffer; 2] {
+    [
+        buffer::create_storage_buffer(
+            device,
+            cell_buffer_size(width, height),
+            Some("Simulation cell storage buffer A")),
+        buffer::create_storage_buffer(
+            device,
+            cell_buffer_size(width, height),
+            Some("Simulation cell storage buffer B")),
+    ]
+}
@@ -0,0 +1,138 @@
+use bevy::app::{App, Plugin};
+use bevy::input::Input;
+use bevy::prelude::{KeyCode, Local, Res, ResMut, Resource};
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy::time::Time;
+use crate::settings::SimulationSettings;
+
+const MAX_SPEED: u32 = 16;
+
+/// The most ticks run in one frame. If a frame takes so long that more are due the rest
+/// are dropped, otherwise each slow frame would make the next one slower still.
+const MAX_TICKS_PER_FRAME: u32 = 16;
+
+pub struct SimulationControlPlugin;
+impl Plugin for SimulationControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationControl>()
+            .add_plugin(ExtractResourcePlugin::<SimulationControl>::default())
+            .add_system(update_simulation_control);
+    }
+}
+
+/// How fast the simulation runs, set from the keyboard:
+/// space pauses, `.` steps once while paused and `-`/`=` change the speed.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct SimulationControl {
+    pub paused: bool,
+    /// Multiplies the tick rate to fast forward the simulation.
+    pub speed: u32,
+    /// Run a single step this frame even though the simulation is paused.
+    pub step_once: bool,
+    /// The ticks due this frame from the time that has passed at the fixed tick rate.
+    pub pending_ticks: u32,
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            speed: 1,
+            step_once: false,
+            pending_ticks: 0,
+        }
+    }
+}
+
+impl SimulationControl {
+    /// The number of simulation steps to run this frame.
+    pub fn ticks_this_frame(&self) -> u32 {
+        if !self.paused {
+            self.pending_ticks
+        } else if self.step_once {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+fn update_simulation_control(
+    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    settings: Res<SimulationSettings>,
+    mut control: ResMut<SimulationControl>,
+    mut accumulated_time: Local<f32>,
+) {
+    // A single step only lasts for the frame it was requested in
+    if control.step_once {
+        control.step_once = false;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        control.paused = !control.paused;
+    }
+    if keyboard_input.just_pressed(KeyCode::Period) && control.paused {
+        control.step_once = true;
+    }
+    if keyboard_input.just_pressed(KeyCode::Equals) {
+        control.speed = (control.speed + 1).min(MAX_SPEED);
+    }
+    if keyboard_input.just_pressed(KeyCode::Minus) {
+        control.speed = control.speed.saturating_sub(1).max(1);
+    }
+
+    if control.paused {
+        *accumulated_time = 0.0;
+        control.pending_ticks = 0;
+        return;
+    }
+
+    let tick_duration = 1.0 / (settings.tick_rate * control.speed as f32);
+    control.pending_ticks = take_ticks(&mut accumulated_time, time.delta_seconds(), tick_duration);
+}
+
+/// Adds `delta` seconds to the time accumulated towards the next tick and takes out the
+/// whole ticks that are due, at most `MAX_TICKS_PER_FRAME` of them.
+fn take_ticks(accumulated_time: &mut f32, delta: f32, tick_duration: f32) -> u32 {
+    *accumulated_time += delta;
+    let ticks = (*accumulated_time / tick_duration) as u32;
+    if ticks > MAX_TICKS_PER_FRAME {
+        *accumulated_time = 0.0;
+        MAX_TICKS_PER_FRAME
+    } else {
+        *accumulated_time -= ticks as f32 * tick_duration;
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_are_taken_once_enough_time_has_passed() {
+        let mut accumulated_time = 0.0;
+        assert_eq!(take_ticks(&mut accumulated_time, 0.25, 0.5), 0);
+        assert_eq!(take_ticks(&mut accumulated_time, 0.25, 0.5), 1);
+        assert_eq!(accumulated_time, 0.0);
+    }
+
+    #[test]
+    fn leftover_time_carries_over_to_the_next_frame() {
+        let mut accumulated_time = 0.0;
+        assert_eq!(take_ticks(&mut accumulated_time, 1.25, 0.5), 2);
+        assert_eq!(accumulated_time, 0.25);
+        assert_eq!(take_ticks(&mut accumulated_time, 0.25, 0.5), 1);
+    }
+
+    #[test]
+    fn slow_frames_drop_ticks_past_the_limit() {
+        let mut accumulated_time = 0.0;
+        let tick_duration = 0.5;
+        let delta = (MAX_TICKS_PER_FRAME + 5) as f32 * tick_duration;
+
+        assert_eq!(take_ticks(&mut accumulated_time, delta, tick_duration), MAX_TICKS_PER_FRAME);
+        assert_eq!(accumulated_time, 0.0);
+    }
+}
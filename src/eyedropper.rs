@@ -0,0 +1,124 @@
+use std::sync::{Arc, Mutex};
+use bevy::app::{App, Plugin};
+use bevy::math::Vec2;
+use bevy::prelude::{IntoSystemConfig, Res, ResMut, Resource};
+use bevy::render::render_resource::{Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, MapMode};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::{RenderApp, RenderSet};
+use crate::cell::Cell;
+use crate::input::DrawingParams;
+use crate::materials::MaterialRegistry;
+use crate::pipeline::cellular_automata::CurrentCellBuffer;
+use crate::settings::SimulationSettings;
+use crate::CellularAutomataBuffers;
+
+/// Middle clicking picks up the material under the cursor into the brush. The cell lives on
+/// the GPU, so it is copied out and read back a frame or two later.
+pub struct EyedropperPlugin;
+impl Plugin for EyedropperPlugin {
+    fn build(&self, app: &mut App) {
+        let picked = PickedMaterial::default();
+        let staging_buffer = app.world.resource::<RenderDevice>().create_buffer(&BufferDescriptor {
+            label: Some("Eyedropper staging buffer"),
+            size: std::mem::size_of::<Cell>() as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        app.insert_resource(picked.clone())
+            .add_system(apply_picked_material);
+
+        app.sub_app_mut(RenderApp)
+            .insert_resource(picked)
+            .insert_resource(EyedropperReadback {
+                staging_buffer,
+                map_result: Arc::default(),
+                waiting: false,
+                queued_pick: None,
+            })
+            .add_system(read_picked_cell.in_set(RenderSet::Prepare));
+    }
+}
+
+/// The material of the last picked cell, handed from the render world to the main world.
+#[derive(Resource, Clone, Default)]
+struct PickedMaterial(Arc<Mutex<Option<u32>>>);
+
+#[derive(Resource)]
+struct EyedropperReadback {
+    staging_buffer: Buffer,
+    /// Set by the map callback to whether the staging buffer could be mapped.
+    map_result: Arc<Mutex<Option<bool>>>,
+    /// A cell has been copied out and not read yet, so the staging buffer is still in use.
+    waiting: bool,
+    /// The latest click made while waiting, picked once the staging buffer is free again.
+    queued_pick: Option<Vec2>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_picked_cell(
+    mut readback: ResMut<EyedropperReadback>,
+    picked: Res<PickedMaterial>,
+    drawing_params: Res<DrawingParams>,
+    settings: Res<SimulationSettings>,
+    buffers: Res<CellularAutomataBuffers>,
+    current_cell_buffer: Res<CurrentCellBuffer>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    let map_result = readback.map_result.lock().unwrap().take();
+    if let Some(mapped) = map_result {
+        if mapped {
+            let cell: Cell = bytemuck::pod_read_unaligned(&readback.staging_buffer.slice(..).get_mapped_range());
+            readback.staging_buffer.unmap();
+            *picked.0.lock().unwrap() = Some(cell.particle_type);
+        }
+        readback.waiting = false;
+    }
+
+    if let Some(pick_at) = drawing_params.pick_at {
+        readback.queued_pick = Some(pick_at);
+    }
+    if readback.waiting {
+        return;
+    }
+    let Some(pick_at) = readback.queued_pick.take() else {
+        return;
+    };
+    let location = pick_at.floor().as_ivec2();
+    if location.x < 0 || location.y < 0 || location.x >= settings.width as i32 || location.y >= settings.height as i32 {
+        return;
+    }
+
+    let cell_size = std::mem::size_of::<Cell>() as u64;
+    let index = location.y as u64 * settings.width as u64 + location.x as u64;
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Eyedropper copy encoder"),
+    });
+    encoder.copy_buffer_to_buffer(
+        &buffers.cell_buffers[current_cell_buffer.0],
+        index * cell_size,
+        &readback.staging_buffer,
+        0,
+        cell_size);
+    queue.submit([encoder.finish()]);
+
+    let map_result = readback.map_result.clone();
+    readback.staging_buffer.slice(..).map_async(MapMode::Read, move |result| {
+        *map_result.lock().unwrap() = Some(result.is_ok());
+    });
+    readback.waiting = true;
+}
+
+fn apply_picked_material(
+    picked: Res<PickedMaterial>,
+    registry: Res<MaterialRegistry>,
+    mut drawing_params: ResMut<DrawingParams>,
+) {
+    let Some(material) = picked.0.lock().unwrap().take() else {
+        return;
+    };
+    if registry.get(material).is_some() {
+        drawing_params.material = material;
+    }
+}
@@ -1,25 +1,204 @@
 use bevy::app::{App, Plugin};
 use bevy::input::ButtonState;
-use bevy::input::mouse::MouseButtonInput;
+use bevy::input::mouse::{MouseButtonInput, MouseScrollUnit, MouseWheel};
 use bevy::math::Vec2;
-use bevy::prelude::{Camera, EventReader, GlobalTransform, MouseButton, Query, ResMut, Resource, With};
+use bevy::prelude::{Button, Camera, EventReader, GlobalTransform, Input, Interaction, IntoSystemConfig, KeyCode, MouseButton, Query, Res, ResMut, Resource, With};
 use bevy::render::extract_resource::ExtractResource;
 use bevy::window::{PrimaryWindow, Window};
 use winit::event::Event;
+use crate::materials;
+use crate::materials::MaterialRegistry;
+use crate::settings::SimulationSettings;
 
 pub struct InputPlugin;
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<DrawingParams>()
-            .add_system(update_input_state);
+            .add_system(update_input_state)
+            .add_system(select_brush_material)
+            .add_system(adjust_brush_radius)
+            .add_system(select_brush_shape)
+            .add_system(select_history_action.after(update_input_state));
     }
 }
 
-#[derive(Default, Resource, ExtractResource, Clone)]
+const MIN_BRUSH_RADIUS: f32 = 1.0;
+const MAX_BRUSH_RADIUS: f32 = 100.0;
+const SPRAY_DENSITY_STEP: f32 = 0.05;
+
+/// Each number key selects the material with that id, so 0 is the air eraser.
+pub const BRUSH_MATERIAL_KEYS: [KeyCode; 10] = [
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// The shape the brush paints, the values match the `BRUSH_*` constants in `core.wgsl`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BrushShape {
+    #[default]
+    Circle,
+    Square,
+    /// A circle that only fills some of the cells under it.
+    Spray,
+    Line,
+    Rectangle,
+    /// Fills the connected region of whatever material was clicked.
+    Bucket,
+}
+
+impl BrushShape {
+    pub const ALL: [BrushShape; 6] = [
+        BrushShape::Circle,
+        BrushShape::Square,
+        BrushShape::Spray,
+        BrushShape::Line,
+        BrushShape::Rectangle,
+        BrushShape::Bucket,
+    ];
+
+    /// Circles, squares and spray paint wherever the mouse goes while it is held.
+    pub fn is_freehand(self) -> bool {
+        matches!(self, BrushShape::Circle | BrushShape::Square | BrushShape::Spray)
+    }
+
+    /// Lines and rectangles are dragged out from where the mouse was pressed and
+    /// only drawn once it is released.
+    pub fn is_drag(self) -> bool {
+        matches!(self, BrushShape::Line | BrushShape::Rectangle)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BrushShape::Circle => "Circle",
+            BrushShape::Square => "Square",
+            BrushShape::Spray => "Spray",
+            BrushShape::Line => "Line",
+            BrushShape::Rectangle => "Rectangle",
+            BrushShape::Bucket => "Bucket",
+        }
+    }
+}
+
+/// Which cells under the brush it is allowed to change, the values match the
+/// `BRUSH_MODE_*` constants in `core.wgsl`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BrushMode {
+    #[default]
+    Overwrite,
+    /// Only paint into empty cells, leaving everything already there alone.
+    FillEmpty,
+    /// Only paint over cells of `DrawingParams::replace_material`.
+    Replace,
+}
+
+impl BrushMode {
+    pub const ALL: [BrushMode; 3] = [
+        BrushMode::Overwrite,
+        BrushMode::FillEmpty,
+        BrushMode::Replace,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BrushMode::Overwrite => "Overwrite",
+            BrushMode::FillEmpty => "Fill empty",
+            BrushMode::Replace => "Replace",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HistoryAction {
+    Undo,
+    Redo,
+}
+
+#[derive(Resource, ExtractResource, Clone)]
 pub struct DrawingParams {
     pub canvas_position: Vec2,
     pub is_drawing: bool,
+    /// The stroke was started with the right mouse button, so paints air.
+    pub erasing: bool,
     pub previous_canvas_position: Vec2,
+    pub material: u32,
+    pub brush_radius: f32,
+    pub brush_shape: BrushShape,
+    pub brush_mode: BrushMode,
+    /// The material the replace mode paints over.
+    pub replace_material: u32,
+    /// The fraction of cells under the spray brush that get filled each frame.
+    pub spray_density: f32,
+    /// Where the current line or rectangle drag began.
+    pub drag_start: Option<Vec2>,
+    /// Draw the dragged line or rectangle, only set for the frame the mouse is released.
+    pub commit_drag: bool,
+    /// Where to flood fill, only set for the frame the bucket was clicked.
+    pub fill_at: Option<Vec2>,
+    /// Set for the frame a stroke, line, rectangle or fill is first drawn, so that
+    /// the cells can be saved for undoing it.
+    pub begins_edit: bool,
+    /// Undo or redo an edit this frame.
+    pub history_action: Option<HistoryAction>,
+    /// Where to pick up the material for the brush from, only set for the frame of the click.
+    pub pick_at: Option<Vec2>,
+}
+
+impl Default for DrawingParams {
+    fn default() -> Self {
+        Self {
+            canvas_position: Vec2::ZERO,
+            is_drawing: false,
+            erasing: false,
+            previous_canvas_position: Vec2::ZERO,
+            material: materials::SAND,
+            brush_radius: 10.0,
+            brush_shape: BrushShape::Circle,
+            brush_mode: BrushMode::Overwrite,
+            replace_material: materials::SAND,
+            spray_density: 0.1,
+            drag_start: None,
+            commit_drag: false,
+            fill_at: None,
+            begins_edit: false,
+            history_action: None,
+            pick_at: None,
+        }
+    }
+}
+
+impl DrawingParams {
+    /// The material the brush is painting with right now.
+    pub fn brush_material(&self) -> u32 {
+        if self.erasing {
+            materials::AIR
+        } else {
+            self.material
+        }
+    }
+
+    /// The start and end of whatever should be drawn this frame.
+    pub fn stroke(&self) -> Option<(Vec2, Vec2)> {
+        if self.commit_drag {
+            self.drag_start.map(|start| (start, self.canvas_position))
+        } else if self.is_drawing && self.brush_shape.is_freehand() {
+            Some((self.previous_canvas_position, self.canvas_position))
+        } else {
+            None
+        }
+    }
+
+    /// The start and end of the brush outline, which follows a drag while there is one.
+    pub fn cursor(&self) -> (Vec2, Vec2) {
+        (self.drag_start.unwrap_or(self.canvas_position), self.canvas_position)
+    }
 }
 
 pub fn update_input_state(
@@ -27,14 +206,40 @@ pub fn update_input_state(
     mut input_state: ResMut<DrawingParams>,
     camera_query: Query<(&Camera, &GlobalTransform), With<crate::MainCamera>>,
     mut mouse_button_input_events: EventReader<MouseButtonInput>,
+    button_query: Query<&Interaction, With<Button>>,
+    settings: Res<SimulationSettings>,
 ) {
     let primary_window = window_query.single();
     let (camera, camera_transform) = camera_query.single();
 
+    // The last drag was drawn on the previous frame
+    if input_state.commit_drag {
+        input_state.commit_drag = false;
+        input_state.drag_start = None;
+    }
+    input_state.fill_at = None;
+    input_state.pick_at = None;
+
+    let mut stroke_started = false;
+    let mut stroke_ended = false;
+    let mut picking = false;
     for event in mouse_button_input_events.iter() {
-        if event.button == MouseButton::Left {
-            let is_drawing = event.state == ButtonState::Pressed;
-            input_state.is_drawing = is_drawing;
+        // Clicking a button in the UI shouldn't also paint underneath it
+        let over_button = button_query.iter().any(|interaction| *interaction != Interaction::None);
+        let pressed = event.state == ButtonState::Pressed && !over_button;
+
+        match event.button {
+            // The left button paints with the selected material and the right one erases
+            MouseButton::Left | MouseButton::Right => {
+                if pressed {
+                    input_state.erasing = event.button == MouseButton::Right;
+                }
+                stroke_started |= pressed && !input_state.is_drawing;
+                stroke_ended |= !pressed && input_state.is_drawing;
+                input_state.is_drawing = pressed;
+            }
+            MouseButton::Middle => picking |= pressed,
+            MouseButton::Other(_) => {}
         }
     }
 
@@ -42,13 +247,132 @@ pub fn update_input_state(
         .and_then(|cursor| camera.viewport_to_world_2d(camera_transform, cursor))
     {
         input_state.previous_canvas_position = input_state.canvas_position;
-        input_state.canvas_position = world_position_to_canvas_position(world_position * Vec2::new(1.0, -1.0));
+        input_state.canvas_position = world_position_to_canvas_position(world_position * Vec2::new(1.0, -1.0), &settings);
+    }
+
+    if picking {
+        input_state.pick_at = Some(input_state.canvas_position);
+    }
+
+    // A new stroke starts where the cursor is rather than joining up with the last one
+    if stroke_started {
+        input_state.previous_canvas_position = input_state.canvas_position;
+        if input_state.brush_shape.is_drag() {
+            input_state.drag_start = Some(input_state.canvas_position);
+        }
+        if input_state.brush_shape == BrushShape::Bucket {
+            input_state.fill_at = Some(input_state.canvas_position);
+        }
+    }
+    if stroke_ended && input_state.drag_start.is_some() {
+        input_state.commit_drag = true;
+    }
+
+    input_state.begins_edit = (stroke_started && input_state.brush_shape.is_freehand())
+        || input_state.commit_drag
+        || input_state.fill_at.is_some();
+}
+
+pub fn select_brush_material(
+    keyboard_input: Res<Input<KeyCode>>,
+    registry: Res<MaterialRegistry>,
+    mut input_state: ResMut<DrawingParams>,
+) {
+    // Holding shift picks the material to replace instead
+    let shift_held = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    for (material, key) in (0..).zip(BRUSH_MATERIAL_KEYS) {
+        if keyboard_input.just_pressed(key) && registry.get(material).is_some() {
+            if shift_held {
+                input_state.replace_material = material;
+            } else {
+                input_state.material = material;
+            }
+        }
+    }
+}
+
+/// `[` and `]` or Ctrl+scroll shrink and grow the brush.
+pub fn adjust_brush_radius(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut input_state: ResMut<DrawingParams>,
+) {
+    let mut change = 0.0;
+    if keyboard_input.just_pressed(KeyCode::LBracket) {
+        change -= 1.0;
+    }
+    if keyboard_input.just_pressed(KeyCode::RBracket) {
+        change += 1.0;
+    }
+
+    let ctrl_held = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    for MouseWheel { y, unit, .. } in mouse_wheel_events.iter() {
+        if ctrl_held {
+            change += match unit {
+                MouseScrollUnit::Line => *y,
+                MouseScrollUnit::Pixel => {
+                    const PIXELS_PER_LINE: f32 = 38.0;
+                    *y / PIXELS_PER_LINE
+                }
+            };
+        }
+    }
+
+    if change != 0.0 {
+        input_state.brush_radius = (input_state.brush_radius + change).clamp(MIN_BRUSH_RADIUS, MAX_BRUSH_RADIUS);
+    }
+}
+
+/// Tab cycles through the brush shapes and M through the brush modes,
+/// `;` and `'` thin out and thicken the spray.
+pub fn select_brush_shape(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut input_state: ResMut<DrawingParams>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        let index = BrushShape::ALL.iter().position(|shape| *shape == input_state.brush_shape).unwrap_or(0);
+        input_state.brush_shape = BrushShape::ALL[(index + 1) % BrushShape::ALL.len()];
+    }
+    if keyboard_input.just_pressed(KeyCode::M) {
+        let index = BrushMode::ALL.iter().position(|mode| *mode == input_state.brush_mode).unwrap_or(0);
+        input_state.brush_mode = BrushMode::ALL[(index + 1) % BrushMode::ALL.len()];
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Semicolon) {
+        input_state.spray_density = (input_state.spray_density - SPRAY_DENSITY_STEP).max(SPRAY_DENSITY_STEP);
+    }
+    if keyboard_input.just_pressed(KeyCode::Apostrophe) {
+        input_state.spray_density = (input_state.spray_density + SPRAY_DENSITY_STEP).min(1.0);
+    }
+}
+
+/// Ctrl+Z undoes the last edit, Ctrl+Y or Ctrl+Shift+Z redoes it. Both are ignored while
+/// something is being drawn, otherwise the rest of the edit would be drawn over the restored
+/// cells, and an edit starting in the same frame couldn't be saved for undoing.
+pub fn select_history_action(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut input_state: ResMut<DrawingParams>,
+) {
+    input_state.history_action = None;
+
+    if !keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        return;
+    }
+    if input_state.is_drawing || input_state.commit_drag || input_state.begins_edit {
+        return;
+    }
+    let shift_held = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+
+    if keyboard_input.just_pressed(KeyCode::Y) || (keyboard_input.just_pressed(KeyCode::Z) && shift_held) {
+        input_state.history_action = Some(HistoryAction::Redo);
+    } else if keyboard_input.just_pressed(KeyCode::Z) {
+        input_state.history_action = Some(HistoryAction::Undo);
     }
 }
 
-fn world_position_to_canvas_position(world_position: Vec2) -> Vec2 {
+fn world_position_to_canvas_position(world_position: Vec2, settings: &SimulationSettings) -> Vec2 {
     world_position + Vec2::new(
-        crate::SIMULATION_SIZE.0 as f32 / 2.0,
-        crate::SIMULATION_SIZE.1 as f32 / 2.0,
+        settings.width as f32 / 2.0,
+        settings.height as f32 / 2.0,
     )
 }
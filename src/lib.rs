@@ -3,10 +3,17 @@ mod audio;
 mod loading;
 mod menu;
 pub mod cellular_automata_image;
+pub mod resize;
 mod pipeline;
 mod camera;
 mod input;
 mod buffer;
+mod cell;
+mod control;
+mod eyedropper;
+mod materials;
+mod palette;
+mod settings;
 
 use bevy::app::App;
 #[cfg(debug_assertions)]
@@ -23,10 +30,12 @@ use bevy::window::PrimaryWindow;
 use crate::actions::ActionsPlugin;
 use crate::audio::InternalAudioPlugin;
 use crate::loading::LoadingPlugin;
+use crate::materials::{MaterialRegistry, MaterialsPlugin};
 use crate::menu::MenuPlugin;
 use crate::cellular_automata_image::CellularAutomataImage;
 use crate::input::DrawingParams;
 use crate::pipeline::PipelinesPlugin;
+use crate::settings::SimulationSettings;
 
 // This example game uses States to separate logic
 // See https://bevy-cheatbook.github.io/programming/states.html
@@ -42,7 +51,6 @@ enum GameState {
     Menu,
 }
 
-const SIMULATION_SIZE: (u32, u32) = (1280, 720);
 const WORKGROUP_SIZE: u32 = 8;
 
 pub struct GamePlugin;
@@ -50,15 +58,25 @@ pub struct GamePlugin;
 #[derive(Component)]
 pub struct MainCamera;
 
+#[derive(Component)]
+pub struct SimulationSprite;
+
 #[derive(Resource, Clone, ExtractResource)]
 pub struct CellularAutomataBuffers {
     pub size_buffer: Buffer,
+    /// The simulation reads from one of these and writes to the other, swapping every pass.
+    pub cell_buffers: [Buffer; 2],
+    pub material_buffer: Buffer,
+    pub reaction_buffer: Buffer,
 }
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
+        let limits = app.world.resource::<RenderDevice>().limits();
         app.add_state::<GameState>()
+            .insert_resource(SimulationSettings::load(&limits))
             .add_startup_system(setup)
+            .add_plugin(MaterialsPlugin)
             .add_plugin(LoadingPlugin)
             .add_plugin(MenuPlugin)
             .add_plugin(ActionsPlugin)
@@ -66,9 +84,15 @@ impl Plugin for GamePlugin {
             .add_plugin(ExtractResourcePlugin::<CellularAutomataImage>::default())
             .add_plugin(ExtractResourcePlugin::<DrawingParams>::default())
             .add_plugin(ExtractResourcePlugin::<CellularAutomataBuffers>::default())
+            .add_plugin(ExtractResourcePlugin::<MaterialRegistry>::default())
+            .add_plugin(ExtractResourcePlugin::<SimulationSettings>::default())
             .add_plugin(camera::CameraPlugin)
             .add_plugin(PipelinesPlugin)
-            .add_plugin(input::InputPlugin);
+            .add_plugin(input::InputPlugin)
+            .add_plugin(palette::PalettePlugin)
+            .add_plugin(eyedropper::EyedropperPlugin)
+            .add_plugin(control::SimulationControlPlugin)
+            .add_plugin(resize::ResizePlugin);
 
         #[cfg(debug_assertions)]
         {
@@ -79,9 +103,16 @@ impl Plugin for GamePlugin {
     }
 }
 
-fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, device: Res<RenderDevice>) {
-    let width: u32 = SIMULATION_SIZE.0;
-    let height: u32 = SIMULATION_SIZE.1;
+fn setup(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    device: Res<RenderDevice>,
+    settings: Res<SimulationSettings>,
+) {
+    info!("Simulation seed is {0}, pass --seed {0} to repeat this run", settings.seed);
+
+    let width: u32 = settings.width;
+    let height: u32 = settings.height;
     let image = cellular_automata_image::create_image(width, height);
     let image = images.add(image);
 
@@ -90,6 +121,18 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, device: Res<
         &[width, height],
         Some("Simulation size uniform buffer"));
 
+    let cell_buffers = cell::create_cell_buffers(&device, width, height);
+
+    let material_buffer = buffer::create_storage_buffer(
+        &device,
+        materials::material_table_size(),
+        Some("Material table storage buffer"));
+
+    let reaction_buffer = buffer::create_storage_buffer(
+        &device,
+        materials::reaction_table_size(),
+        Some("Reaction table storage buffer"));
+
     commands.spawn(SpriteBundle {
         sprite: Sprite {
             custom_size: Some(Vec2::new(width as f32, height as f32)),
@@ -97,11 +140,11 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>, device: Res<
         },
         texture: image.clone(),
         ..default()
-    });
+    }).insert(SimulationSprite);
 
     commands.spawn((Camera2dBundle::default(), MainCamera));
     commands.insert_resource(CellularAutomataImage(image));
-    commands.insert_resource(CellularAutomataBuffers { size_buffer });
+    commands.insert_resource(CellularAutomataBuffers { size_buffer, cell_buffers, material_buffer, reaction_buffer });
 }
 
 fn display_fps(diagnostics: Res<Diagnostics>, mut windows: Query<&mut Window, With<PrimaryWindow>>) {
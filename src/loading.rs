@@ -1,3 +1,4 @@
+use crate::materials::MaterialDefinitions;
 use crate::GameState;
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
@@ -15,7 +16,8 @@ impl Plugin for LoadingPlugin {
         )
         .add_collection_to_loading_state::<_, FontAssets>(GameState::Loading)
         .add_collection_to_loading_state::<_, AudioAssets>(GameState::Loading)
-        .add_collection_to_loading_state::<_, TextureAssets>(GameState::Loading);
+        .add_collection_to_loading_state::<_, TextureAssets>(GameState::Loading)
+        .add_collection_to_loading_state::<_, MaterialAssets>(GameState::Loading);
     }
 }
 
@@ -39,3 +41,11 @@ pub struct TextureAssets {
     #[asset(path = "textures/bevy.png")]
     pub texture_bevy: Handle<Image>,
 }
+
+#[derive(AssetCollection, Resource)]
+pub struct MaterialAssets {
+    // Only held to keep the definitions loaded, the registry picks them up from asset events
+    #[allow(dead_code)]
+    #[asset(path = "default.materials.ron")]
+    pub materials: Handle<MaterialDefinitions>,
+}
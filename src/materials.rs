@@ -0,0 +1,490 @@
+use bevy::app::{App, Plugin};
+use bevy::asset::{AddAsset, AssetEvent, AssetLoader, Assets, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::log::error;
+use bevy::prelude::{EventReader, Res, ResMut, Resource};
+use bevy::reflect::TypeUuid;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::renderer::RenderQueue;
+use serde::Deserialize;
+
+use crate::CellularAutomataBuffers;
+
+/// The number of materials the GPU material table has room for.
+pub const MAX_MATERIALS: usize = 256;
+
+/// The number of reactions the GPU reaction table has room for.
+pub const MAX_REACTIONS: usize = 256;
+
+// Ids of the built in materials, see assets/default.materials.ron
+pub const AIR: u32 = 0;
+pub const SAND: u32 = 1;
+
+pub struct MaterialsPlugin;
+impl Plugin for MaterialsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<MaterialDefinitions>()
+            .init_asset_loader::<MaterialDefinitionsLoader>()
+            .init_resource::<MaterialRegistry>()
+            .add_system(update_material_registry);
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StateOfMatter {
+    Solid,
+    Powder,
+    Liquid,
+    Gas,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MaterialDefinition {
+    pub id: u32,
+    pub name: String,
+    pub colour: [f32; 4],
+    pub density: f32,
+    pub state: StateOfMatter,
+    #[serde(default)]
+    pub flammability: f32,
+    /// How many cells a liquid may spread, or a gas drift, sideways in one step.
+    #[serde(default)]
+    pub dispersion: u32,
+    /// Roughly how many steps a cell lasts before turning into `decays_into`, 0 lasts forever.
+    #[serde(default)]
+    pub lifetime: u32,
+    #[serde(default)]
+    pub decays_into: u32,
+    /// What is left once a cell of this material has burnt.
+    #[serde(default)]
+    pub burns_into: u32,
+    /// What a cell of this material turns into while it burns, usually fire.
+    #[serde(default)]
+    pub burns_as: u32,
+    /// Whether the material sets light to flammable cells next to it, as fire does.
+    #[serde(default)]
+    pub ignites: bool,
+    /// How much the brightness of each cell flickers, from 0 to 1.
+    #[serde(default)]
+    pub flicker: f32,
+    /// The temperature new cells start at, in degrees Celsius.
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
+    /// How readily heat flows through the material, from 0 for an insulator to 1.
+    #[serde(default)]
+    pub conductivity: f32,
+    #[serde(default)]
+    pub melting_point: Option<f32>,
+    #[serde(default)]
+    pub melts_into: u32,
+    #[serde(default)]
+    pub boiling_point: Option<f32>,
+    #[serde(default)]
+    pub boils_into: u32,
+    #[serde(default)]
+    pub freezing_point: Option<f32>,
+    #[serde(default)]
+    pub freezes_into: u32,
+    /// The temperature above which the material catches fire by itself, turning into
+    /// `burns_as` just as if a flame had lit it.
+    #[serde(default)]
+    pub ignition_point: Option<f32>,
+    /// How much the temperature of a cell can change in one step without damaging it,
+    /// for brittle materials.
+    #[serde(default)]
+    pub shatter_point: Option<f32>,
+    /// How many damaging steps a brittle cell survives before it cracks into `shatters_into`.
+    #[serde(default)]
+    pub integrity: u32,
+    #[serde(default)]
+    pub shatters_into: u32,
+    /// Whether pulses of electricity travel through the material.
+    #[serde(default)]
+    pub conducts_electricity: bool,
+    /// Whether the material is always charged, sending pulses into conductors it touches.
+    #[serde(default)]
+    pub powered: bool,
+}
+
+fn default_temperature() -> f32 {
+    20.0
+}
+
+impl MaterialDefinition {
+    /// The temperature above which the material melts or boils, and what into.
+    fn heat_limit(&self) -> Option<(f32, u32)> {
+        self.melting_point
+            .map(|point| (point, self.melts_into))
+            .or(self.boiling_point.map(|point| (point, self.boils_into)))
+    }
+}
+
+/// Two neighbouring cells of the `reactants` turning into the `products`, in the same order,
+/// with the chance `probability` each step.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ReactionDefinition {
+    pub reactants: (u32, u32),
+    pub products: (u32, u32),
+    pub probability: f32,
+}
+
+/// The contents of a `.materials.ron` asset, such as `default.materials.ron`.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "5c3a6f0e-9d1b-4c07-8a51-2f6e4d8b7a93"]
+pub struct MaterialDefinitions {
+    pub materials: Vec<MaterialDefinition>,
+    /// The material past the edges of the simulation, which it also starts with a floor of.
+    pub wall: u32,
+    #[serde(default)]
+    pub reactions: Vec<ReactionDefinition>,
+}
+
+#[derive(Default)]
+pub struct MaterialDefinitionsLoader;
+impl AssetLoader for MaterialDefinitionsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let definitions = ron::de::from_bytes::<MaterialDefinitions>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(definitions));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["materials.ron"]
+    }
+}
+
+/// Every material known to the simulation, indexed by its id, and the reactions between them.
+#[derive(Resource, Default, Clone, ExtractResource)]
+pub struct MaterialRegistry {
+    materials: Vec<Option<MaterialDefinition>>,
+    reactions: Vec<ReactionDefinition>,
+    wall: u32,
+}
+
+impl MaterialRegistry {
+    /// Builds the registry from the loaded definitions, skipping anything invalid, along with
+    /// a description of each problem found.
+    fn from_definitions(definitions: &MaterialDefinitions) -> (Self, Vec<String>) {
+        let mut problems = Vec::new();
+        let mut materials: Vec<Option<MaterialDefinition>> = Vec::new();
+        for material in &definitions.materials {
+            let index = material.id as usize;
+            if index >= MAX_MATERIALS {
+                problems.push(format!("Material '{}' has id {} but the maximum is {}", material.name, material.id, MAX_MATERIALS - 1));
+                continue;
+            }
+            if materials.len() <= index {
+                materials.resize(index + 1, None);
+            }
+            if let Some(existing) = &materials[index] {
+                problems.push(format!("Material '{}' reuses the id {} of '{}'", material.name, material.id, existing.name));
+                continue;
+            }
+            materials[index] = Some(material.clone());
+        }
+
+        if !matches!(materials.first(), Some(Some(_))) {
+            problems.push("No material with id 0 is defined, it is used for empty cells".to_string());
+        }
+
+        for material in materials.iter().flatten() {
+            let references = [
+                ("decays_into", material.decays_into),
+                ("burns_into", material.burns_into),
+                ("burns_as", material.burns_as),
+                ("melts_into", material.melts_into),
+                ("boils_into", material.boils_into),
+                ("freezes_into", material.freezes_into),
+                ("shatters_into", material.shatters_into),
+            ];
+            for (field, id) in references {
+                if !matches!(materials.get(id as usize), Some(Some(_))) {
+                    problems.push(format!("Material '{}' {} unknown material {}", material.name, field, id));
+                }
+            }
+            if material.melting_point.is_some() && material.boiling_point.is_some() {
+                problems.push(format!("Material '{}' has both a melting and a boiling point, only the melting point is used", material.name));
+            }
+        }
+
+        let is_defined = |id: u32| matches!(materials.get(id as usize), Some(Some(_)));
+        if !is_defined(definitions.wall) {
+            problems.push(format!("The wall is unknown material {}", definitions.wall));
+        }
+
+        let mut reactions = Vec::new();
+        for reaction in &definitions.reactions {
+            let (first, second) = reaction.reactants;
+            let (first_product, second_product) = reaction.products;
+            if let Some(id) = [first, second, first_product, second_product].into_iter().find(|id| !is_defined(*id)) {
+                problems.push(format!("Reaction {:?} uses unknown material {}", reaction, id));
+                continue;
+            }
+            if reactions.len() == MAX_REACTIONS {
+                problems.push(format!("Only {} reactions are allowed, ignoring the rest", MAX_REACTIONS));
+                break;
+            }
+            reactions.push(reaction.clone());
+        }
+
+        (MaterialRegistry { materials, reactions, wall: definitions.wall }, problems)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&MaterialDefinition> {
+        self.materials.get(id as usize).and_then(Option::as_ref)
+    }
+
+    /// The material past the edges of the simulation.
+    pub fn wall(&self) -> u32 {
+        self.wall
+    }
+
+    /// Every defined material in order of id.
+    pub fn iter(&self) -> impl Iterator<Item = &MaterialDefinition> {
+        self.materials.iter().flatten()
+    }
+
+    /// The largest dispersion of any material, which is how many update passes each tick needs.
+    pub fn max_dispersion(&self) -> u32 {
+        self.iter()
+            .map(|material| material.dispersion)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn gpu_table(&self) -> Vec<GpuMaterial> {
+        self.materials
+            .iter()
+            .map(|material| material.as_ref().map(GpuMaterial::from).unwrap_or_default())
+            .collect()
+    }
+
+    /// The reaction table as laid out in the reaction buffer, the number of reactions
+    /// followed by the reactions themselves.
+    fn gpu_reaction_table(&self) -> Vec<u8> {
+        let reactions: Vec<GpuReaction> = self.reactions.iter().map(GpuReaction::from).collect();
+        let mut table = bytemuck::bytes_of(&(reactions.len() as u32)).to_vec();
+        table.extend_from_slice(bytemuck::cast_slice(&reactions));
+        table
+    }
+}
+
+/// A material as seen by the shaders, laid out to match `Material` in `core.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuMaterial {
+    colour: [f32; 4],
+    density: f32,
+    state: u32,
+    flammability: f32,
+    dispersion: u32,
+    lifetime: u32,
+    decays_into: u32,
+    burns_into: u32,
+    burns_as: u32,
+    ignites: u32,
+    ignition_limit: f32,
+    flicker: f32,
+    temperature: f32,
+    conductivity: f32,
+    heat_limit: f32,
+    heats_into: u32,
+    cold_limit: f32,
+    cools_into: u32,
+    shatter_limit: f32,
+    shatters_into: u32,
+    electricity: u32,
+    integrity: u32,
+    _padding: [u32; 3],
+}
+
+impl From<&MaterialDefinition> for GpuMaterial {
+    fn from(material: &MaterialDefinition) -> Self {
+        Self {
+            colour: material.colour,
+            density: material.density,
+            state: material.state as u32,
+            flammability: material.flammability,
+            dispersion: material.dispersion,
+            lifetime: material.lifetime,
+            decays_into: material.decays_into,
+            burns_into: material.burns_into,
+            burns_as: material.burns_as,
+            ignites: material.ignites as u32,
+            ignition_limit: material.ignition_point.unwrap_or(f32::MAX),
+            flicker: material.flicker,
+            temperature: material.temperature,
+            conductivity: material.conductivity,
+            heat_limit: material.heat_limit().map_or(f32::MAX, |(point, _)| point),
+            heats_into: material.heat_limit().map_or(0, |(_, into)| into),
+            cold_limit: material.freezing_point.unwrap_or(f32::MIN),
+            cools_into: material.freezes_into,
+            shatter_limit: material.shatter_point.unwrap_or(f32::MAX),
+            shatters_into: material.shatters_into,
+            electricity: material.conducts_electricity as u32 | (material.powered as u32) << 1,
+            integrity: material.integrity,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// A reaction as seen by the shaders, laid out to match `Reaction` in `falling_sand.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuReaction {
+    first: u32,
+    second: u32,
+    first_product: u32,
+    second_product: u32,
+    probability: f32,
+}
+
+impl From<&ReactionDefinition> for GpuReaction {
+    fn from(reaction: &ReactionDefinition) -> Self {
+        Self {
+            first: reaction.reactants.0,
+            second: reaction.reactants.1,
+            first_product: reaction.products.0,
+            second_product: reaction.products.1,
+            probability: reaction.probability,
+        }
+    }
+}
+
+pub fn material_table_size() -> u64 {
+    (MAX_MATERIALS * std::mem::size_of::<GpuMaterial>()) as u64
+}
+
+pub fn reaction_table_size() -> u64 {
+    (std::mem::size_of::<u32>() + MAX_REACTIONS * std::mem::size_of::<GpuReaction>()) as u64
+}
+
+fn update_material_registry(
+    mut events: EventReader<AssetEvent<MaterialDefinitions>>,
+    definitions: Res<Assets<MaterialDefinitions>>,
+    mut registry: ResMut<MaterialRegistry>,
+    buffers: Res<CellularAutomataBuffers>,
+    render_queue: Res<RenderQueue>,
+) {
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                if let Some(definitions) = definitions.get(handle) {
+                    let (new_registry, problems) = MaterialRegistry::from_definitions(definitions);
+                    for problem in problems {
+                        error!("{}", problem);
+                    }
+                    *registry = new_registry;
+                    render_queue.write_buffer(&buffers.material_buffer, 0, bytemuck::cast_slice(&registry.gpu_table()));
+                    render_queue.write_buffer(&buffers.reaction_buffer, 0, &registry.gpu_reaction_table());
+                }
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_materials_are_valid() {
+        let definitions: MaterialDefinitions = ron::de::from_str(include_str!("../assets/default.materials.ron"))
+            .expect("assets/default.materials.ron should parse");
+        let (registry, problems) = MaterialRegistry::from_definitions(&definitions);
+
+        assert!(problems.is_empty(), "assets/default.materials.ron has problems: {:#?}", problems);
+        assert_eq!(registry.iter().count(), definitions.materials.len());
+        assert_eq!(registry.reactions.len(), definitions.reactions.len());
+    }
+
+    fn material(id: u32, name: &str) -> MaterialDefinition {
+        ron::de::from_str(&format!("(id: {id}, name: \"{name}\", colour: (1.0, 1.0, 1.0, 1.0), density: 1.0, state: Solid)"))
+            .unwrap()
+    }
+
+    fn definitions(materials: Vec<MaterialDefinition>) -> MaterialDefinitions {
+        MaterialDefinitions { materials, wall: 0, reactions: Vec::new() }
+    }
+
+    #[test]
+    fn reused_ids_keep_the_first_material() {
+        let (registry, problems) = MaterialRegistry::from_definitions(&definitions(vec![
+            material(0, "Air"),
+            material(1, "Sand"),
+            material(1, "Salt"),
+        ]));
+
+        assert_eq!(problems, ["Material 'Salt' reuses the id 1 of 'Sand'"]);
+        assert_eq!(registry.get(1).unwrap().name, "Sand");
+    }
+
+    #[test]
+    fn ids_past_the_material_table_are_skipped() {
+        let (registry, problems) = MaterialRegistry::from_definitions(&definitions(vec![
+            material(0, "Air"),
+            material(MAX_MATERIALS as u32, "Sand"),
+        ]));
+
+        assert_eq!(problems, [format!("Material 'Sand' has id {} but the maximum is {}", MAX_MATERIALS, MAX_MATERIALS - 1)]);
+        assert_eq!(registry.iter().count(), 1);
+    }
+
+    #[test]
+    fn air_must_be_defined() {
+        let mut definitions = definitions(vec![material(1, "Sand")]);
+        definitions.wall = 1;
+        let (_, problems) = MaterialRegistry::from_definitions(&definitions);
+
+        assert!(problems.contains(&"No material with id 0 is defined, it is used for empty cells".to_string()), "{:#?}", problems);
+    }
+
+    #[test]
+    fn unknown_materials_are_reported() {
+        let mut sand = material(1, "Sand");
+        sand.decays_into = 2;
+        sand.burns_into = 3;
+        sand.burns_as = 4;
+        let mut definitions = definitions(vec![material(0, "Air"), sand]);
+        definitions.wall = 5;
+        let (_, problems) = MaterialRegistry::from_definitions(&definitions);
+
+        assert_eq!(problems, [
+            "Material 'Sand' decays_into unknown material 2",
+            "Material 'Sand' burns_into unknown material 3",
+            "Material 'Sand' burns_as unknown material 4",
+            "The wall is unknown material 5",
+        ]);
+    }
+
+    fn reaction(reactants: (u32, u32), products: (u32, u32)) -> ReactionDefinition {
+        ReactionDefinition { reactants, products, probability: 1.0 }
+    }
+
+    #[test]
+    fn reactions_with_unknown_materials_are_skipped() {
+        let mut definitions = definitions(vec![material(0, "Air"), material(1, "Sand")]);
+        definitions.reactions = vec![reaction((0, 1), (1, 0)), reaction((0, 1), (1, 2))];
+        let (registry, problems) = MaterialRegistry::from_definitions(&definitions);
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].ends_with("uses unknown material 2"), "{}", problems[0]);
+        assert_eq!(registry.reactions.len(), 1);
+    }
+
+    #[test]
+    fn reactions_past_the_reaction_table_are_skipped() {
+        let mut definitions = definitions(vec![material(0, "Air"), material(1, "Sand")]);
+        definitions.reactions = vec![reaction((0, 1), (1, 0)); MAX_REACTIONS + 1];
+        let (registry, problems) = MaterialRegistry::from_definitions(&definitions);
+
+        assert_eq!(problems, [format!("Only {} reactions are allowed, ignoring the rest", MAX_REACTIONS)]);
+        assert_eq!(registry.reactions.len(), MAX_REACTIONS);
+    }
+}
@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+
+use crate::GameState;
+use crate::input::{BrushMode, BrushShape, DrawingParams, BRUSH_MATERIAL_KEYS};
+use crate::loading::FontAssets;
+use crate::materials::MaterialRegistry;
+
+const PALETTE_NORMAL: Color = Color::rgba(0.15, 0.15, 0.15, 0.8);
+const PALETTE_HOVERED: Color = Color::rgba(0.25, 0.25, 0.25, 0.8);
+const PALETTE_SELECTED: Color = Color::rgba(0.45, 0.45, 0.45, 0.9);
+const PALETTE_REPLACED: Color = Color::rgba(0.5, 0.2, 0.2, 0.9);
+
+const PALETTE_BUTTON_WIDTH: f32 = 140.0;
+const PALETTE_BUTTON_MARGIN: f32 = 2.0;
+/// How many buttons sit side by side before wrapping onto the next row.
+const PALETTE_COLUMNS: usize = 2;
+
+/// A grid of buttons down the side of the screen, one for each brush shape, brush mode and
+/// material in the registry, which choose how and with what the brush paints. The brush
+/// buttons and the material buttons are grouped separately, a couple of columns wide so
+/// that every material fits on screen.
+/// Shift clicking a material picks it as the one the replace mode paints over.
+pub struct PalettePlugin;
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            (rebuild_palette, click_palette_button, update_palette_colours)
+                .chain()
+                .in_set(OnUpdate(GameState::Playing)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct Palette;
+
+#[derive(Component, Clone, Copy)]
+enum PaletteButton {
+    Shape(BrushShape),
+    Mode(BrushMode),
+    Material(u32),
+}
+
+impl PaletteButton {
+    fn is_selected(self, drawing_params: &DrawingParams) -> bool {
+        match self {
+            PaletteButton::Shape(shape) => drawing_params.brush_shape == shape,
+            PaletteButton::Mode(mode) => drawing_params.brush_mode == mode,
+            PaletteButton::Material(material) => drawing_params.material == material,
+        }
+    }
+
+    fn is_replaced(self, drawing_params: &DrawingParams) -> bool {
+        match self {
+            PaletteButton::Material(material) => drawing_params.brush_mode == BrushMode::Replace
+                && drawing_params.replace_material == material,
+            _ => false,
+        }
+    }
+
+    fn select(self, drawing_params: &mut DrawingParams, shift_held: bool) {
+        match self {
+            PaletteButton::Shape(shape) => drawing_params.brush_shape = shape,
+            PaletteButton::Mode(mode) => drawing_params.brush_mode = mode,
+            PaletteButton::Material(material) if shift_held => drawing_params.replace_material = material,
+            PaletteButton::Material(material) => drawing_params.material = material,
+        }
+    }
+}
+
+/// Spawns the palette, and again whenever the materials are reloaded.
+fn rebuild_palette(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    registry: Res<MaterialRegistry>,
+    palette_query: Query<Entity, With<Palette>>,
+) {
+    if !registry.is_changed() && !palette_query.is_empty() {
+        return;
+    }
+
+    for palette in &palette_query {
+        commands.entity(palette).despawn_recursive();
+    }
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::Column,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Palette)
+        .with_children(|parent| {
+            spawn_palette_section(parent, |parent| {
+                for shape in BrushShape::ALL {
+                    spawn_palette_button(parent, &font_assets, PaletteButton::Shape(shape), shape.name().to_string(), None);
+                }
+
+                for mode in BrushMode::ALL {
+                    spawn_palette_button(parent, &font_assets, PaletteButton::Mode(mode), mode.name().to_string(), None);
+                }
+            });
+
+            spawn_palette_section(parent, |parent| {
+                for material in registry.iter() {
+                    // Show the number key for the materials that have one
+                    let label = if (material.id as usize) < BRUSH_MATERIAL_KEYS.len() {
+                        format!("{} {}", material.id, material.name)
+                    } else {
+                        material.name.clone()
+                    };
+                    let [r, g, b, a] = material.colour;
+                    let swatch = Color::rgba(r, g, b, a.max(0.2));
+                    spawn_palette_button(parent, &font_assets, PaletteButton::Material(material.id), label, Some(swatch));
+                }
+            });
+        });
+}
+
+/// A group of buttons laid out in rows of `PALETTE_COLUMNS`.
+fn spawn_palette_section(parent: &mut ChildBuilder, spawn_buttons: impl FnOnce(&mut ChildBuilder)) {
+    let width = PALETTE_COLUMNS as f32 * (PALETTE_BUTTON_WIDTH + 2.0 * PALETTE_BUTTON_MARGIN);
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::width(Val::Px(width)),
+                margin: UiRect::bottom(Val::Px(8.0)),
+                flex_direction: FlexDirection::Row,
+                flex_wrap: FlexWrap::Wrap,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with_children(spawn_buttons);
+}
+
+fn spawn_palette_button(
+    parent: &mut ChildBuilder,
+    font_assets: &FontAssets,
+    button: PaletteButton,
+    label: String,
+    swatch: Option<Color>,
+) {
+    parent
+        .spawn(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(PALETTE_BUTTON_WIDTH), Val::Px(28.0)),
+                margin: UiRect::all(Val::Px(PALETTE_BUTTON_MARGIN)),
+                padding: UiRect::all(Val::Px(4.0)),
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            background_color: PALETTE_NORMAL.into(),
+            ..Default::default()
+        })
+        .insert(button)
+        .with_children(|parent| {
+            if let Some(swatch) = swatch {
+                parent.spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(20.0), Val::Px(20.0)),
+                        margin: UiRect::right(Val::Px(6.0)),
+                        ..Default::default()
+                    },
+                    background_color: swatch.into(),
+                    ..Default::default()
+                });
+            }
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font_assets.fira_sans.clone(),
+                    font_size: 18.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                },
+            ));
+        });
+}
+
+fn click_palette_button(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut drawing_params: ResMut<DrawingParams>,
+    interaction_query: Query<(&Interaction, &PaletteButton), Changed<Interaction>>,
+) {
+    let shift_held = keyboard_input.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    for (interaction, button) in &interaction_query {
+        if *interaction == Interaction::Clicked {
+            button.select(&mut drawing_params, shift_held);
+        }
+    }
+}
+
+fn update_palette_colours(
+    drawing_params: Res<DrawingParams>,
+    mut button_query: Query<(&Interaction, &PaletteButton, &mut BackgroundColor)>,
+) {
+    for (interaction, button, mut colour) in &mut button_query {
+        *colour = if button.is_selected(&drawing_params) {
+            PALETTE_SELECTED.into()
+        } else if button.is_replaced(&drawing_params) {
+            PALETTE_REPLACED.into()
+        } else if *interaction == Interaction::Hovered {
+            PALETTE_HOVERED.into()
+        } else {
+            PALETTE_NORMAL.into()
+        };
+    }
+}
@@ -1,10 +1,14 @@
 use bevy::app::App;
 use bevy::prelude::Plugin;
 use bevy::render::render_graph::RenderGraph;
+use bevy::render::render_resource::ComputePass;
 use bevy::render::RenderApp;
+use crate::WORKGROUP_SIZE;
 
 pub mod cellular_automata;
 pub mod drawing;
+pub mod flood_fill;
+pub mod history;
 
 pub struct PipelinesPlugin;
 impl Plugin for PipelinesPlugin {
@@ -12,12 +16,26 @@ impl Plugin for PipelinesPlugin {
         let render_app = app.sub_app_mut(RenderApp);
         render_app
             .add_plugin(drawing::DrawingPipelinePlugin)
+            .add_plugin(flood_fill::FloodFillPipelinePlugin)
             .add_plugin(cellular_automata::CellularAutomataPipelinePlugin);
 
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
         let automata_id = render_graph.add_node("falling_sand", cellular_automata::CellularAutomataNode::default());
         let drawing_id = render_graph.add_node("drawing", drawing::DrawingNode::default());
-        render_graph.add_node_edge(drawing_id, automata_id);
+        let flood_fill_id = render_graph.add_node("flood_fill", flood_fill::FloodFillNode::default());
+        render_graph.add_node_edge(drawing_id, flood_fill_id);
+        render_graph.add_node_edge(flood_fill_id, automata_id);
         render_graph.add_node_edge(automata_id, bevy::render::main_graph::node::CAMERA_DRIVER);
     }
 }
+
+/// Dispatches enough workgroups for one invocation per item of a `width` by `height` grid.
+/// The count is rounded up so that no edge rows or columns are skipped, which means shaders
+/// must check invocations against the simulation size.
+pub fn dispatch_workgroups_for(pass: &mut ComputePass, width: u32, height: u32) {
+    pass.dispatch_workgroups(
+        width.div_ceil(WORKGROUP_SIZE),
+        height.div_ceil(WORKGROUP_SIZE),
+        1,
+    );
+}
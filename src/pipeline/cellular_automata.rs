@@ -7,22 +7,79 @@ use bevy::render::{render_graph, RenderSet};
 use bevy::render::render_graph::{NodeRunError, RenderGraphContext, SlotInfo};
 use bevy::render::render_resource::*;
 use bevy::render::renderer::{RenderContext, RenderDevice};
+use crate::cell::Cell;
 use crate::cellular_automata_image::CellularAutomataImage;
-use crate::{CellularAutomataBuffers, SIMULATION_SIZE, WORKGROUP_SIZE};
+use crate::materials::{GpuMaterial, GpuReaction, MaterialRegistry};
+use crate::control::SimulationControl;
+use crate::input::{BrushShape, DrawingParams};
+use crate::settings::SimulationSettings;
+use crate::CellularAutomataBuffers;
+use super::dispatch_workgroups_for;
+use super::flood_fill::FloodFillJob;
 
 pub struct CellularAutomataPipelinePlugin;
 impl Plugin for CellularAutomataPipelinePlugin {
     fn build(&self, render_app: &mut App) {
         render_app
             .init_resource::<CellularAutomataPipeline>()
+            .init_resource::<CurrentCellBuffer>()
             .add_system(queue_bind_group.in_set(RenderSet::Queue));
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SimulationPushConstants {
+    frame: u32,
+    pass_index: u32,
+    seed: u32,
+    wall: u32,
+}
+
+impl SimulationPushConstants {
+    pub fn new(frame: u32, pass_index: u32, seed: u32, wall: u32) -> Self {
+        Self {
+            frame,
+            pass_index,
+            seed,
+            wall,
+        }
+    }
+}
+
+/// Where to outline the brush when colourising, or the drag to preview.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CursorPushConstants {
+    start: [f32; 2],
+    end: [f32; 2],
+    radius: f32,
+    shape: u32,
+    material: u32,
+    preview: u32,
+}
+
+impl CursorPushConstants {
+    pub fn new(drawing_params: &DrawingParams) -> Self {
+        let (start, end) = drawing_params.cursor();
+        Self {
+            start: start.to_array(),
+            end: end.to_array(),
+            // The bucket only picks the cell under the cursor
+            radius: if drawing_params.brush_shape == BrushShape::Bucket { 0.0 } else { drawing_params.brush_radius },
+            shape: drawing_params.brush_shape as u32,
+            material: drawing_params.brush_material(),
+            preview: drawing_params.drag_start.is_some() as u32,
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct CellularAutomataPipeline {
     init_pipeline: CachedComputePipelineId,
     update_pipeline: CachedComputePipelineId,
+    heat_pipeline: CachedComputePipelineId,
+    colourise_pipeline: CachedComputePipelineId,
     bind_group_layout: BindGroupLayout,
 }
 
@@ -46,11 +103,51 @@ impl FromWorld for CellularAutomataPipeline {
                         binding: 1,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
+                            access: StorageTextureAccess::WriteOnly,
                             format: TextureFormat::Rgba8Unorm,
                             view_dimension: TextureViewDimension::D2,
                         },
                         count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<Cell>() as _),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<GpuMaterial>() as _),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<Cell>() as _),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new((std::mem::size_of::<u32>() + std::mem::size_of::<GpuReaction>()) as _),
+                        },
+                        count: None,
                     }
                 ],
             });
@@ -60,11 +157,16 @@ impl FromWorld for CellularAutomataPipeline {
             .resource::<AssetServer>()
             .load("shaders/falling_sand.wgsl");
 
+        let simulation_push_constants = vec![PushConstantRange {
+            stages: ShaderStages::COMPUTE,
+            range: 0..std::mem::size_of::<SimulationPushConstants>() as u32,
+        }];
+
         let init_pipeline = pipeline_cache.queue_compute_pipeline(
             ComputePipelineDescriptor {
                 label: Some(Cow::from("Falling sand init pipeline")),
                 layout: vec![bind_group_layout.clone()],
-                push_constant_ranges: vec![],
+                push_constant_ranges: simulation_push_constants.clone(),
                 shader: shader.clone(),
                 shader_defs: vec![],
                 entry_point: Cow::from("init")
@@ -75,23 +177,57 @@ impl FromWorld for CellularAutomataPipeline {
             ComputePipelineDescriptor {
                 label: Some(Cow::from("Falling sand update pipeline")),
                 layout: vec![bind_group_layout.clone()],
-                push_constant_ranges: vec![],
-                shader,
+                push_constant_ranges: simulation_push_constants.clone(),
+                shader: shader.clone(),
                 shader_defs: vec![],
                 entry_point: Cow::from("update"),
             }
         );
 
+        let heat_pipeline = pipeline_cache.queue_compute_pipeline(
+            ComputePipelineDescriptor {
+                label: Some(Cow::from("Falling sand heat pipeline")),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: simulation_push_constants,
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: Cow::from("heat"),
+            }
+        );
+
+        let colourise_pipeline = pipeline_cache.queue_compute_pipeline(
+            ComputePipelineDescriptor {
+                label: Some(Cow::from("Falling sand colourise pipeline")),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: vec![PushConstantRange {
+                    stages: ShaderStages::COMPUTE,
+                    range: 0..std::mem::size_of::<CursorPushConstants>() as u32,
+                }],
+                shader,
+                shader_defs: vec![],
+                entry_point: Cow::from("colourise"),
+            }
+        );
+
         CellularAutomataPipeline {
             bind_group_layout,
             init_pipeline,
             update_pipeline,
+            heat_pipeline,
+            colourise_pipeline,
         }
     }
 }
 
+/// Bind groups for both directions of the ping-pong between the cell buffers.
+/// The first reads the buffer holding the latest state and writes the other one,
+/// the second goes back the other way.
 #[derive(Resource)]
-pub(crate) struct CellularAutomataImageBindGroup(pub BindGroup);
+pub(crate) struct CellularAutomataImageBindGroup(pub [BindGroup; 2]);
+
+/// Which of `CellularAutomataBuffers::cell_buffers` holds the latest simulation state.
+#[derive(Resource, Default)]
+pub struct CurrentCellBuffer(pub usize);
 
 pub fn queue_bind_group(
     mut commands: Commands,
@@ -100,22 +236,44 @@ pub fn queue_bind_group(
     gpu_images: Res<RenderAssets<Image>>,
     cellular_automata_image: Res<CellularAutomataImage>,
     buffers: Res<CellularAutomataBuffers>,
+    current_cell_buffer: Res<CurrentCellBuffer>,
 ) {
     let view = &gpu_images[&cellular_automata_image.0];
-    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-        label: Some("Cellular Automata Bind Group"),
-        layout: &pipeline.bind_group_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: buffers.size_buffer.as_entire_binding(),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::TextureView(&view.texture_view),
-        }],
-    });
-    commands.insert_resource(CellularAutomataImageBindGroup(bind_group))
+    let create_bind_group = |source: usize| {
+        render_device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Cellular Automata Bind Group"),
+            layout: &pipeline.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffers.size_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&view.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.cell_buffers[source].as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: buffers.material_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: buffers.cell_buffers[1 - source].as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: buffers.reaction_buffer.as_entire_binding(),
+            }],
+        })
+    };
+
+    let current = current_cell_buffer.0;
+    let bind_groups = [create_bind_group(current), create_bind_group(1 - current)];
+    commands.insert_resource(CellularAutomataImageBindGroup(bind_groups))
 }
 
 pub enum CellularAutomataState {
@@ -126,12 +284,31 @@ pub enum CellularAutomataState {
 
 pub struct CellularAutomataNode {
     state: CellularAutomataState,
+    frame: u32,
+    ticks: u32,
+    flow_passes: u32,
+    wall: u32,
 }
 
 impl Default for CellularAutomataNode {
     fn default() -> Self {
         Self {
             state: CellularAutomataState::Loading,
+            frame: 0,
+            ticks: 0,
+            flow_passes: 0,
+            wall: 0,
+        }
+    }
+}
+
+impl CellularAutomataNode {
+    /// The number of times this frame's passes write to the cell buffers.
+    fn passes(&self) -> u32 {
+        match self.state {
+            CellularAutomataState::Loading => 0,
+            CellularAutomataState::Init => 1,
+            CellularAutomataState::Update => self.ticks * (2 + self.flow_passes),
         }
     }
 }
@@ -143,19 +320,45 @@ impl render_graph::Node for CellularAutomataNode {
 
         match self.state {
             CellularAutomataState::Loading => {
+                // The starting floor is made of the wall material, so wait for the materials too
+                let materials_loaded = world.resource::<MaterialRegistry>().iter().next().is_some();
                 if let CachedPipelineState::Ok(_) =
                     pipeline_cache.get_compute_pipeline_state(pipeline.init_pipeline) {
-                    self.state = CellularAutomataState::Init;
+                    if materials_loaded {
+                        self.state = CellularAutomataState::Init;
+                    }
                 }
             }
             CellularAutomataState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline) {
+                let update_state = pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline);
+                let heat_state = pipeline_cache.get_compute_pipeline_state(pipeline.heat_pipeline);
+                let colourise_state = pipeline_cache.get_compute_pipeline_state(pipeline.colourise_pipeline);
+                if let (CachedPipelineState::Ok(_), CachedPipelineState::Ok(_), CachedPipelineState::Ok(_)) =
+                    (update_state, heat_state, colourise_state) {
                     self.state = CellularAutomataState::Update;
                 }
             }
-            CellularAutomataState::Update => {}
+            CellularAutomataState::Update => {
+                // Each tick of the simulation counts as a frame, the update blocks alternate
+                // their offset every one
+                self.frame = self.frame.wrapping_add(self.ticks);
+            }
         }
+
+        // Hold still while a fill is spreading so the region being filled doesn't move
+        self.ticks = if world.resource::<FloodFillJob>().is_filling() {
+            0
+        } else {
+            world.resource::<SimulationControl>().ticks_this_frame()
+        };
+        // The update pass moves cells sideways by one, anything that spreads further
+        // needs that many more passes of it
+        self.flow_passes = world.resource::<MaterialRegistry>().max_dispersion().saturating_sub(1);
+        self.wall = world.resource::<MaterialRegistry>().wall();
+
+        // Every pass writes the other cell buffer, so work out where the latest state
+        // will be once this frame's passes have run
+        world.resource_mut::<CurrentCellBuffer>().0 ^= (self.passes() % 2) as usize;
     }
 
     fn run(
@@ -164,39 +367,71 @@ impl render_graph::Node for CellularAutomataNode {
         render_context: &mut RenderContext,
         world: &World
     ) -> Result<(), NodeRunError> {
-        let texture_bind_group = &world.resource::<CellularAutomataImageBindGroup>().0;
+        let bind_groups = &world.resource::<CellularAutomataImageBindGroup>().0;
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<CellularAutomataPipeline>();
+        let settings = world.resource::<SimulationSettings>();
+        let drawing_params = world.resource::<DrawingParams>();
 
         let mut pass = render_context
             .command_encoder()
             .begin_compute_pass(&ComputePassDescriptor::default());
 
-        pass.set_bind_group(0, texture_bind_group, &[]);
-
         match self.state {
             CellularAutomataState::Loading => {}
             CellularAutomataState::Init => {
                 let init_pipeline = pipeline_cache
                     .get_compute_pipeline(pipeline.init_pipeline)
                     .unwrap();
+                let push_constants = SimulationPushConstants::new(self.frame, 0, settings.seed, self.wall);
                 pass.set_pipeline(init_pipeline);
-                pass.dispatch_workgroups(
-                    SIMULATION_SIZE.0 / WORKGROUP_SIZE,
-                    SIMULATION_SIZE.1 / WORKGROUP_SIZE,
-                    1,
-                );
+                pass.set_bind_group(0, &bind_groups[0], &[]);
+                pass.set_push_constants(0, bytemuck::cast_slice(&[push_constants]));
+                dispatch_workgroups_for(&mut pass, settings.width, settings.height);
             }
             CellularAutomataState::Update => {
                 let update_pipeline = pipeline_cache
                     .get_compute_pipeline(pipeline.update_pipeline)
                     .unwrap();
-                pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(
-                    SIMULATION_SIZE.0 / WORKGROUP_SIZE,
-                    SIMULATION_SIZE.1 / WORKGROUP_SIZE,
-                    1,
-                );
+                let heat_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.heat_pipeline)
+                    .unwrap();
+
+                let mut passes = 0;
+                for tick in 0..self.ticks {
+                    let frame = self.frame.wrapping_add(tick);
+
+                    // The first pass runs the whole update, the rest only move liquids and
+                    // gases further sideways
+                    pass.set_pipeline(update_pipeline);
+                    for pass_index in 0..=self.flow_passes {
+                        let push_constants = SimulationPushConstants::new(frame, pass_index, settings.seed, self.wall);
+                        pass.set_bind_group(0, &bind_groups[passes % 2], &[]);
+                        pass.set_push_constants(0, bytemuck::cast_slice(&[push_constants]));
+                        // Each invocation updates a 2x2 block, with an extra row and column of
+                        // blocks to cover the edges when the blocks are offset
+                        dispatch_workgroups_for(&mut pass, settings.width / 2 + 1, settings.height / 2 + 1);
+                        passes += 1;
+                    }
+
+                    let push_constants = SimulationPushConstants::new(frame, 0, settings.seed, self.wall);
+                    pass.set_pipeline(heat_pipeline);
+                    pass.set_bind_group(0, &bind_groups[passes % 2], &[]);
+                    pass.set_push_constants(0, bytemuck::cast_slice(&[push_constants]));
+                    dispatch_workgroups_for(&mut pass, settings.width, settings.height);
+                    passes += 1;
+                }
+
+                // The texture is only a render target, so redraw it from the latest cells,
+                // even when paused so that drawing still shows up
+                let colourise_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.colourise_pipeline)
+                    .unwrap();
+                let push_constants = CursorPushConstants::new(drawing_params);
+                pass.set_pipeline(colourise_pipeline);
+                pass.set_bind_group(0, &bind_groups[passes % 2], &[]);
+                pass.set_push_constants(0, bytemuck::cast_slice(&[push_constants]));
+                dispatch_workgroups_for(&mut pass, settings.width, settings.height);
             }
         }
 
@@ -1,17 +1,19 @@
 use std::borrow::Cow;
 use bevy::app::{App, Plugin};
 use bevy::math::Vec2;
-use bevy::prelude::{AssetServer, Commands, FromWorld, Image, IntoSystemConfig, Res, Resource, World};
-use bevy::render::render_asset::RenderAssets;
+use bevy::prelude::{AssetServer, Commands, FromWorld, IntoSystemConfig, Res, Resource, World};
 use bevy::render::render_phase::Draw;
-use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferSize, CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, PushConstantRange, ShaderStages, StorageTextureAccess, TextureFormat, TextureViewDimension};
+use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferSize, CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, PushConstantRange, ShaderStages};
 use bevy::render::renderer::{RenderContext, RenderDevice};
 use bevy::render::{render_graph, RenderSet};
 use bevy::render::render_graph::{NodeRunError, RenderGraphContext};
-use crate::cellular_automata_image::CellularAutomataImage;
+use crate::cell::Cell;
 use crate::input::DrawingParams;
-use crate::{CellularAutomataBuffers, SIMULATION_SIZE, WORKGROUP_SIZE};
-use super::cellular_automata::CellularAutomataImageBindGroup;
+use crate::CellularAutomataBuffers;
+use super::dispatch_workgroups_for;
+use crate::settings::SimulationSettings;
+use super::cellular_automata::CurrentCellBuffer;
+use super::history::UndoHistory;
 
 pub struct DrawingPipelinePlugin;
 impl Plugin for DrawingPipelinePlugin {
@@ -28,12 +30,26 @@ pub struct DrawingPushConstants {
     draw_start: [f32; 2],
     draw_end: [f32; 2],
     draw_radius: f32,
+    material: u32,
+    brush_shape: u32,
+    spray_density: f32,
+    seed: u32,
+    frame: u32,
+    brush_mode: u32,
+    replace_material: u32,
 }
 
 impl DrawingPushConstants {
-    pub fn new(draw_start: Vec2, draw_end: Vec2, draw_radius: f32) -> Self {
+    pub fn new(draw_start: Vec2, draw_end: Vec2, drawing_params: &DrawingParams, seed: u32, frame: u32) -> Self {
         Self {
-            draw_radius,
+            draw_radius: drawing_params.brush_radius,
+            material: drawing_params.brush_material(),
+            brush_shape: drawing_params.brush_shape as u32,
+            spray_density: drawing_params.spray_density,
+            seed,
+            frame,
+            brush_mode: drawing_params.brush_mode as u32,
+            replace_material: drawing_params.replace_material,
             draw_start: draw_start.to_array(),
             draw_end: draw_end.to_array(),
         }
@@ -68,10 +84,10 @@ impl FromWorld for DrawingPipeline {
                     BindGroupLayoutEntry {
                         binding: 1,
                         visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
-                            view_dimension: TextureViewDimension::D2,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<Cell>() as _),
                         },
                         count: None,
                     }
@@ -100,17 +116,20 @@ impl FromWorld for DrawingPipeline {
 }
 
 #[derive(Resource)]
-struct DrawingBindGroup(pub BindGroup);
+struct DrawingBindGroup {
+    bind_group: BindGroup,
+    /// The cell buffer the bind group draws into.
+    cell_buffer: Buffer,
+}
 
 pub fn queue_drawing_bind_group(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
     pipeline: Res<DrawingPipeline>,
-    gpu_images: Res<RenderAssets<Image>>,
-    cellular_automata_image: Res<CellularAutomataImage>,
     buffers: Res<CellularAutomataBuffers>,
+    current_cell_buffer: Res<CurrentCellBuffer>,
 ) {
-    let view = &gpu_images[&cellular_automata_image.0];
+    let cell_buffer = buffers.cell_buffers[current_cell_buffer.0].clone();
     let drawing_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
         label: Some("Drawing bind group"),
         layout: &pipeline.drawing_bind_group_layout,
@@ -121,11 +140,11 @@ pub fn queue_drawing_bind_group(
             },
             BindGroupEntry {
                 binding: 1,
-                resource: BindingResource::TextureView(&view.texture_view),
+                resource: cell_buffer.as_entire_binding(),
             }
         ],
     });
-    commands.insert_resource(DrawingBindGroup(drawing_bind_group))
+    commands.insert_resource(DrawingBindGroup { bind_group: drawing_bind_group, cell_buffer })
 }
 
 pub enum DrawingState {
@@ -135,12 +154,17 @@ pub enum DrawingState {
 
 pub struct DrawingNode {
     state: DrawingState,
+    /// Counts frames so the spray brush picks different cells every frame.
+    frame: u32,
+    history: UndoHistory,
 }
 
 impl Default for DrawingNode {
     fn default() -> Self {
         Self {
             state: DrawingState::Loading,
+            frame: 0,
+            history: UndoHistory::default(),
         }
     }
 }
@@ -159,23 +183,27 @@ impl render_graph::Node for DrawingNode {
             }
             DrawingState::Update => {}
         }
+
+        self.frame = self.frame.wrapping_add(1);
+        self.history.update(_world);
     }
 
     fn run(&self, graph: &mut RenderGraphContext, render_context: &mut RenderContext, world: &World) -> Result<(), NodeRunError> {
         let drawing_params = &world.resource::<DrawingParams>();
+        let drawing_bind_group = world.resource::<DrawingBindGroup>();
+
+        // Snapshot or restore the cells before anything is drawn on them
+        self.history.record(render_context.command_encoder(), &drawing_bind_group.cell_buffer);
 
-        if drawing_params.is_drawing {
-            let texture_bind_group = &world.resource::<CellularAutomataImageBindGroup>().0;
-            let drawing_bind_group = &world.resource::<DrawingBindGroup>().0;
+        if let Some((start, end)) = drawing_params.stroke() {
             let pipeline_cache = world.resource::<PipelineCache>();
             let pipeline = world.resource::<DrawingPipeline>();
+            let settings = world.resource::<SimulationSettings>();
 
             let mut pass = render_context
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor::default());
 
-            pass.set_bind_group(0, texture_bind_group, &[]);
-
             match self.state {
                 DrawingState::Loading => {}
                 DrawingState::Update => {
@@ -183,20 +211,13 @@ impl render_graph::Node for DrawingNode {
                         .get_compute_pipeline(pipeline.drawing_pipeline)
                         .unwrap();
 
-                    let brush_size: f32 = 10.0;
                     let push_constants =
-                    DrawingPushConstants::new(drawing_params.canvas_position,
-                                              drawing_params.previous_canvas_position,
-                                              brush_size);
+                    DrawingPushConstants::new(start, end, drawing_params, settings.seed, self.frame);
 
                     pass.set_pipeline(drawing_pipeline);
-                    pass.set_bind_group(0, drawing_bind_group, &[]);
+                    pass.set_bind_group(0, &drawing_bind_group.bind_group, &[]);
                     pass.set_push_constants(0, bytemuck::cast_slice(&[push_constants]));
-                    pass.dispatch_workgroups(
-                        SIMULATION_SIZE.0 / WORKGROUP_SIZE,
-                        SIMULATION_SIZE.1 / WORKGROUP_SIZE,
-                        1,
-                    );
+                    dispatch_workgroups_for(&mut pass, settings.width, settings.height);
                 }
             }
         }
@@ -0,0 +1,431 @@
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use bevy::app::{App, Plugin};
+use bevy::math::IVec2;
+use bevy::prelude::{AssetServer, Commands, FromWorld, IntoSystemConfig, Res, ResMut, Resource, World};
+use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferSize, BufferUsages, CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor, MapMode, PipelineCache, PushConstantRange, ShaderStages};
+use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use bevy::render::{render_graph, RenderSet};
+use bevy::render::render_graph::{NodeRunError, RenderGraphContext};
+use crate::buffer;
+use crate::cell::Cell;
+use crate::input::DrawingParams;
+use crate::settings::SimulationSettings;
+use crate::CellularAutomataBuffers;
+use super::cellular_automata::CurrentCellBuffer;
+use super::dispatch_workgroups_for;
+
+pub struct FloodFillPipelinePlugin;
+impl Plugin for FloodFillPipelinePlugin {
+    fn build(&self, render_app: &mut App) {
+        render_app
+            .init_resource::<FloodFillPipeline>()
+            .init_resource::<FloodFillStatus>()
+            .init_resource::<FloodFillJob>()
+            .add_system(prepare_flood_fill_marks.in_set(RenderSet::Prepare))
+            .add_system(advance_flood_fill.in_set(RenderSet::Prepare))
+            .add_system(queue_flood_fill_bind_group.in_set(RenderSet::Queue));
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FloodFillPushConstants {
+    start: [i32; 2],
+    material: u32,
+    _padding: u32,
+}
+
+impl FloodFillPushConstants {
+    pub fn new(start: IVec2, material: u32) -> Self {
+        Self {
+            start: start.to_array(),
+            material,
+            _padding: 0,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct FloodFillPipeline {
+    seed_pipeline: CachedComputePipelineId,
+    propagate_pipeline: CachedComputePipelineId,
+    apply_pipeline: CachedComputePipelineId,
+    bind_group_layout: BindGroupLayout,
+}
+
+impl FromWorld for FloodFillPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let bind_group_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Flood fill bind group layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new((2 * std::mem::size_of::<u32>()) as _,),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<Cell>() as _),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(std::mem::size_of::<u32>() as _),
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(FLOOD_FILL_STATUS_SIZE),
+                        },
+                        count: None,
+                    }
+                ],
+            });
+
+        let shader = world.resource::<AssetServer>().load("shaders/flood_fill.wgsl");
+        let push_constant_ranges = vec![PushConstantRange {
+            stages: ShaderStages::COMPUTE,
+            range: 0..std::mem::size_of::<FloodFillPushConstants>() as u32,
+        }];
+
+        let queue_pipeline = |label: &'static str, entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(Cow::from(label)),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: push_constant_ranges.clone(),
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: Cow::from(entry_point),
+            })
+        };
+
+        let seed_pipeline = queue_pipeline("Flood fill seed pipeline", "seed");
+        let propagate_pipeline = queue_pipeline("Flood fill propagate pipeline", "propagate");
+        let apply_pipeline = queue_pipeline("Flood fill apply pipeline", "apply");
+
+        FloodFillPipeline {
+            seed_pipeline,
+            propagate_pipeline,
+            apply_pipeline,
+            bind_group_layout,
+        }
+    }
+}
+
+/// One flag per cell for whether the fill has reached it, sized to match the simulation.
+#[derive(Resource)]
+struct FloodFillMarks {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+}
+
+fn prepare_flood_fill_marks(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    settings: Res<SimulationSettings>,
+    marks: Option<Res<FloodFillMarks>>,
+) {
+    if let Some(marks) = marks {
+        if marks.width == settings.width && marks.height == settings.height {
+            return;
+        }
+    }
+
+    let buffer = buffer::create_storage_buffer(
+        &render_device,
+        settings.width as u64 * settings.height as u64 * std::mem::size_of::<u32>() as u64,
+        Some("Flood fill mark storage buffer"));
+    commands.insert_resource(FloodFillMarks { buffer, width: settings.width, height: settings.height });
+}
+
+/// The size of `FloodFillStatus` in `flood_fill.wgsl`, the material being filled over
+/// followed by whether any propagate pass has marked a cell since it was last cleared.
+const FLOOD_FILL_STATUS_SIZE: u64 = 2 * std::mem::size_of::<u32>() as u64;
+const FLOOD_FILL_CHANGED_OFFSET: u64 = std::mem::size_of::<u32>() as u64;
+
+/// How many propagate passes a fill gets each frame. Every pass carries the fill at least
+/// across one workgroup tile, and the passes carry on over the next frames until the fill
+/// stops growing.
+const PROPAGATE_PASSES_PER_FRAME: u32 = 16;
+
+/// The status of the fill on the GPU, and a buffer to read back whether it is still growing.
+#[derive(Resource)]
+struct FloodFillStatus {
+    buffer: Buffer,
+    staging_buffer: Buffer,
+    /// Set by the map callback to whether the staging buffer could be mapped.
+    map_result: Arc<Mutex<Option<bool>>>,
+    /// The changed flag was copied out last frame, so the staging buffer can be mapped.
+    copied: bool,
+    /// The staging buffer is being mapped and can't be copied into.
+    waiting: bool,
+}
+
+impl FromWorld for FloodFillStatus {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let buffer = buffer::create_storage_buffer(device, FLOOD_FILL_STATUS_SIZE, Some("Flood fill status storage buffer"));
+        let staging_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Flood fill status staging buffer"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        FloodFillStatus {
+            buffer,
+            staging_buffer,
+            map_result: Arc::default(),
+            copied: false,
+            waiting: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FillStep {
+    Seed,
+    Propagate,
+    Apply,
+}
+
+#[derive(Clone, Copy)]
+struct Fill {
+    start: IVec2,
+    material: u32,
+    step: FillStep,
+    /// Whether this frame's passes are followed by copying out the changed flag.
+    read_status: bool,
+}
+
+/// The fill in progress, if any. It can take several frames to cover a winding region, and
+/// the simulation holds still until it is finished so that the region doesn't move under it.
+#[derive(Resource, Default)]
+pub struct FloodFillJob(Option<Fill>);
+
+impl FloodFillJob {
+    pub fn is_filling(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// Starts a fill when the bucket is clicked, reads back whether the last batch of
+/// propagate passes marked anything, and applies the fill once one hasn't.
+fn advance_flood_fill(
+    mut job: ResMut<FloodFillJob>,
+    mut status: ResMut<FloodFillStatus>,
+    drawing_params: Res<DrawingParams>,
+    settings: Res<SimulationSettings>,
+    queue: Res<RenderQueue>,
+) {
+    match job.0.as_ref().map(|fill| fill.step) {
+        Some(FillStep::Seed) => job.0.as_mut().unwrap().step = FillStep::Propagate,
+        Some(FillStep::Apply) => job.0 = None,
+        _ => {}
+    }
+
+    // The copy was submitted with last frame's commands, so the buffer can be mapped now
+    if status.copied {
+        let map_result = status.map_result.clone();
+        status.staging_buffer.slice(..).map_async(MapMode::Read, move |result| {
+            *map_result.lock().unwrap() = Some(result.is_ok());
+        });
+        status.copied = false;
+        status.waiting = true;
+    }
+
+    let map_result = status.map_result.lock().unwrap().take();
+    if let Some(mapped) = map_result {
+        let mut changed = true;
+        if mapped {
+            let flag: u32 = bytemuck::pod_read_unaligned(&status.staging_buffer.slice(..).get_mapped_range());
+            status.staging_buffer.unmap();
+            changed = flag != 0;
+        }
+        status.waiting = false;
+
+        if let Some(fill) = job.0.as_mut() {
+            if !changed && fill.step == FillStep::Propagate {
+                fill.step = FillStep::Apply;
+            }
+        }
+    }
+
+    if job.0.is_none() {
+        if let Some(fill_at) = drawing_params.fill_at {
+            let start = fill_at.floor().as_ivec2();
+            if start.x >= 0 && start.y >= 0 && start.x < settings.width as i32 && start.y < settings.height as i32 {
+                job.0 = Some(Fill {
+                    start,
+                    material: drawing_params.brush_material(),
+                    step: FillStep::Seed,
+                    read_status: false,
+                });
+            }
+        }
+    }
+
+    let Some(fill) = job.0.as_mut() else {
+        return;
+    };
+    fill.read_status = fill.step != FillStep::Apply && !status.waiting;
+    if fill.read_status {
+        queue.write_buffer(&status.buffer, FLOOD_FILL_CHANGED_OFFSET, bytemuck::bytes_of(&0u32));
+        status.copied = true;
+    }
+}
+
+#[derive(Resource)]
+struct FloodFillBindGroup(pub BindGroup);
+
+fn queue_flood_fill_bind_group(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline: Res<FloodFillPipeline>,
+    buffers: Res<CellularAutomataBuffers>,
+    current_cell_buffer: Res<CurrentCellBuffer>,
+    marks: Res<FloodFillMarks>,
+    status: Res<FloodFillStatus>,
+) {
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Flood fill bind group"),
+        layout: &pipeline.bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: buffers.size_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffers.cell_buffers[current_cell_buffer.0].as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: marks.buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: status.buffer.as_entire_binding(),
+            }
+        ],
+    });
+    commands.insert_resource(FloodFillBindGroup(bind_group))
+}
+
+pub enum FloodFillState {
+    Loading,
+    Update,
+}
+
+pub struct FloodFillNode {
+    state: FloodFillState,
+}
+
+impl Default for FloodFillNode {
+    fn default() -> Self {
+        Self {
+            state: FloodFillState::Loading,
+        }
+    }
+}
+
+impl render_graph::Node for FloodFillNode {
+    fn update(&mut self, world: &mut World) {
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<FloodFillPipeline>();
+
+        match self.state {
+            FloodFillState::Loading => {
+                let seed_state = pipeline_cache.get_compute_pipeline_state(pipeline.seed_pipeline);
+                let propagate_state = pipeline_cache.get_compute_pipeline_state(pipeline.propagate_pipeline);
+                let apply_state = pipeline_cache.get_compute_pipeline_state(pipeline.apply_pipeline);
+                if let (CachedPipelineState::Ok(_), CachedPipelineState::Ok(_), CachedPipelineState::Ok(_)) =
+                    (seed_state, propagate_state, apply_state) {
+                    self.state = FloodFillState::Update;
+                }
+            }
+            FloodFillState::Update => {}
+        }
+    }
+
+    fn run(&self, _graph: &mut RenderGraphContext, render_context: &mut RenderContext, world: &World) -> Result<(), NodeRunError> {
+        let settings = world.resource::<SimulationSettings>();
+        let Some(fill) = world.resource::<FloodFillJob>().0 else {
+            return Ok(());
+        };
+
+        match self.state {
+            FloodFillState::Loading => {}
+            FloodFillState::Update => {
+                let bind_group = &world.resource::<FloodFillBindGroup>().0;
+                let status = world.resource::<FloodFillStatus>();
+                let pipeline_cache = world.resource::<PipelineCache>();
+                let pipeline = world.resource::<FloodFillPipeline>();
+                let seed_pipeline = pipeline_cache.get_compute_pipeline(pipeline.seed_pipeline).unwrap();
+                let propagate_pipeline = pipeline_cache.get_compute_pipeline(pipeline.propagate_pipeline).unwrap();
+                let apply_pipeline = pipeline_cache.get_compute_pipeline(pipeline.apply_pipeline).unwrap();
+
+                {
+                    let mut pass = render_context
+                        .command_encoder()
+                        .begin_compute_pass(&ComputePassDescriptor::default());
+
+                    let push_constants = FloodFillPushConstants::new(fill.start, fill.material);
+                    pass.set_bind_group(0, bind_group, &[]);
+                    pass.set_push_constants(0, bytemuck::cast_slice(&[push_constants]));
+
+                    if fill.step == FillStep::Seed {
+                        pass.set_pipeline(seed_pipeline);
+                        dispatch_workgroups_for(&mut pass, settings.width, settings.height);
+                    }
+
+                    if fill.step == FillStep::Apply {
+                        pass.set_pipeline(apply_pipeline);
+                        dispatch_workgroups_for(&mut pass, settings.width, settings.height);
+                    } else {
+                        pass.set_pipeline(propagate_pipeline);
+                        for _ in 0..PROPAGATE_PASSES_PER_FRAME {
+                            dispatch_workgroups_for(&mut pass, settings.width, settings.height);
+                        }
+                    }
+                }
+
+                if fill.read_status {
+                    render_context.command_encoder().copy_buffer_to_buffer(
+                        &status.buffer,
+                        FLOOD_FILL_CHANGED_OFFSET,
+                        &status.staging_buffer,
+                        0,
+                        std::mem::size_of::<u32>() as u64);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
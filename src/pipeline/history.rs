@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+use bevy::math::UVec2;
+use bevy::prelude::World;
+use bevy::render::render_resource::{Buffer, CommandEncoder};
+use bevy::render::renderer::RenderDevice;
+use crate::buffer;
+use crate::cell;
+use crate::input::{DrawingParams, HistoryAction};
+use crate::settings::SimulationSettings;
+
+/// The most memory all the snapshots together may take up, however deep the history is set.
+const UNDO_MEMORY_BUDGET: u64 = 512 * 1024 * 1024;
+
+/// Copies of the cells taken just before each edit, so edits can be undone and redone.
+/// Every snapshot is a whole copy of the cell buffer, so how many are kept is limited by both
+/// `SimulationSettings::undo_depth` and `UNDO_MEMORY_BUDGET`.
+#[derive(Default)]
+pub struct UndoHistory {
+    undo: VecDeque<Buffer>,
+    redo: Vec<Buffer>,
+    /// The simulation size the snapshots were taken at.
+    size: UVec2,
+    pending: Option<PendingCopy>,
+}
+
+/// The copies `UndoHistory::record` makes this frame.
+enum PendingCopy {
+    /// Save the cells as they are before they are drawn on.
+    Snapshot(Buffer),
+    /// Save the cells into `save` and then replace them with `restore`.
+    Restore { save: Buffer, restore: Buffer },
+}
+
+impl UndoHistory {
+    /// Works out what this frame's edits and undo or redo presses need copying.
+    pub fn update(&mut self, world: &World) {
+        let settings = world.resource::<SimulationSettings>();
+        let drawing_params = world.resource::<DrawingParams>();
+        let device = world.resource::<RenderDevice>();
+
+        self.pending = None;
+
+        // Snapshots from before a resize no longer fit the cell buffers
+        let size = UVec2::new(settings.width, settings.height);
+        if size != self.size {
+            self.undo.clear();
+            self.redo.clear();
+            self.size = size;
+        }
+
+        let snapshot_size = cell::cell_buffer_size(settings.width, settings.height);
+        let max_snapshots = (settings.undo_depth as u64).min(UNDO_MEMORY_BUDGET / snapshot_size) as usize;
+        if max_snapshots == 0 {
+            return;
+        }
+        let create_snapshot = || buffer::create_storage_buffer(device, snapshot_size, Some("Undo snapshot storage buffer"));
+
+        match drawing_params.history_action {
+            Some(HistoryAction::Undo) => {
+                if let Some(restore) = self.undo.pop_back() {
+                    let save = create_snapshot();
+                    self.redo.push(save.clone());
+                    self.pending = Some(PendingCopy::Restore { save, restore });
+                }
+            }
+            Some(HistoryAction::Redo) => {
+                if let Some(restore) = self.redo.pop() {
+                    let save = create_snapshot();
+                    self.undo.push_back(save.clone());
+                    self.pending = Some(PendingCopy::Restore { save, restore });
+                }
+            }
+            None if drawing_params.begins_edit => {
+                // A new edit can't be followed by redoing ones that were undone before it
+                self.redo.clear();
+                while self.undo.len() >= max_snapshots {
+                    self.undo.pop_front();
+                }
+
+                let snapshot = create_snapshot();
+                self.undo.push_back(snapshot.clone());
+                self.pending = Some(PendingCopy::Snapshot(snapshot));
+            }
+            None => {}
+        }
+    }
+
+    /// Records this frame's copies, `cells` being the buffer that is about to be drawn on.
+    pub fn record(&self, encoder: &mut CommandEncoder, cells: &Buffer) {
+        let size = cell::cell_buffer_size(self.size.x, self.size.y);
+        match &self.pending {
+            Some(PendingCopy::Snapshot(snapshot)) => {
+                encoder.copy_buffer_to_buffer(cells, 0, snapshot, 0, size);
+            }
+            Some(PendingCopy::Restore { save, restore }) => {
+                encoder.copy_buffer_to_buffer(cells, 0, save, 0, size);
+                encoder.copy_buffer_to_buffer(restore, 0, cells, 0, size);
+            }
+            None => {}
+        }
+    }
+}
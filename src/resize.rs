@@ -0,0 +1,255 @@
+use bevy::app::{App, Plugin};
+use bevy::input::Input;
+use bevy::math::{IVec2, UVec2};
+use bevy::prelude::{
+    Assets, EventReader, EventWriter, Image, IntoSystemConfig, KeyCode, Query, Res, ResMut, Resource, Sprite, With,
+};
+use bevy::render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy::render::render_resource::{Buffer, CommandEncoderDescriptor};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::{RenderApp, RenderSet};
+use crate::cell::{self, Cell};
+use crate::cellular_automata_image::{self, CellularAutomataImage};
+use crate::pipeline::cellular_automata::CurrentCellBuffer;
+use crate::settings::{clamp_size, SimulationSettings};
+use crate::{CellularAutomataBuffers, SimulationSprite};
+
+/// How many cells Alt and an arrow key grow or shrink the simulation by.
+const RESIZE_STEP: u32 = 64;
+
+pub struct ResizePlugin;
+impl Plugin for ResizePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ResizeSimulation>()
+            .init_resource::<PendingCellCopy>()
+            .add_plugin(ExtractResourcePlugin::<PendingCellCopy>::default())
+            .add_system(resize_from_keyboard)
+            .add_system(resize_simulation.after(resize_from_keyboard));
+
+        app.sub_app_mut(RenderApp)
+            .add_system(copy_resized_cells.in_set(RenderSet::Prepare));
+    }
+}
+
+/// Send this to change the size of the simulation while it is running.
+/// Existing cells are kept, positioned in the new grid according to `anchor`.
+pub struct ResizeSimulation {
+    pub width: u32,
+    pub height: u32,
+    pub anchor: ResizeAnchor,
+}
+
+/// Which part of the old grid stays in place when the simulation is resized.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ResizeAnchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Centre,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl ResizeAnchor {
+    /// Where the old grid's top left cell ends up in the new grid.
+    fn offset(&self, old_size: UVec2, new_size: UVec2) -> IVec2 {
+        let difference = new_size.as_ivec2() - old_size.as_ivec2();
+        let (x, y) = match self {
+            ResizeAnchor::TopLeft => (0, 0),
+            ResizeAnchor::Top => (difference.x / 2, 0),
+            ResizeAnchor::TopRight => (difference.x, 0),
+            ResizeAnchor::Left => (0, difference.y / 2),
+            ResizeAnchor::Centre => (difference.x / 2, difference.y / 2),
+            ResizeAnchor::Right => (difference.x, difference.y / 2),
+            ResizeAnchor::BottomLeft => (0, difference.y),
+            ResizeAnchor::Bottom => (difference.x / 2, difference.y),
+            ResizeAnchor::BottomRight => (difference.x, difference.y),
+        };
+        IVec2::new(x, y)
+    }
+}
+
+/// Holding Alt, the left and right arrow keys shrink and grow the width of the simulation,
+/// and down and up its height. The bottom of the grid stays put so nothing falls.
+fn resize_from_keyboard(
+    keyboard_input: Res<Input<KeyCode>>,
+    settings: Res<SimulationSettings>,
+    mut events: EventWriter<ResizeSimulation>,
+) {
+    if !keyboard_input.any_pressed([KeyCode::LAlt, KeyCode::RAlt]) {
+        return;
+    }
+
+    let mut width = settings.width;
+    let mut height = settings.height;
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        width += RESIZE_STEP;
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        width = width.saturating_sub(RESIZE_STEP).max(RESIZE_STEP);
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        height += RESIZE_STEP;
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        height = height.saturating_sub(RESIZE_STEP).max(RESIZE_STEP);
+    }
+
+    if width != settings.width || height != settings.height {
+        events.send(ResizeSimulation {
+            width,
+            height,
+            anchor: ResizeAnchor::Bottom,
+        });
+    }
+}
+
+/// The cells that still need copying from the old buffers into the resized ones.
+/// Only set for the frame in which the resize happened.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct PendingCellCopy(Option<CellCopy>);
+
+#[derive(Clone)]
+struct CellCopy {
+    old_buffers: [Buffer; 2],
+    old_size: UVec2,
+    new_size: UVec2,
+    offset: IVec2,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn resize_simulation(
+    mut events: EventReader<ResizeSimulation>,
+    mut pending_copy: ResMut<PendingCellCopy>,
+    mut settings: ResMut<SimulationSettings>,
+    mut buffers: ResMut<CellularAutomataBuffers>,
+    mut images: ResMut<Assets<Image>>,
+    mut sprites: Query<&mut Sprite, With<SimulationSprite>>,
+    cellular_automata_image: Res<CellularAutomataImage>,
+    device: Res<RenderDevice>,
+) {
+    if pending_copy.0.is_some() {
+        pending_copy.0 = None;
+    }
+
+    // Only the latest size matters if several resizes arrive at once
+    let Some(event) = events.iter().last() else {
+        return;
+    };
+
+    let old_size = UVec2::new(settings.width, settings.height);
+    let new_size = clamp_size(UVec2::new(event.width, event.height), &device.limits());
+    if old_size == new_size {
+        return;
+    }
+
+    settings.width = new_size.x;
+    settings.height = new_size.y;
+
+    if let Some(image) = images.get_mut(&cellular_automata_image.0) {
+        *image = cellular_automata_image::create_image(new_size.x, new_size.y);
+    }
+    for mut sprite in sprites.iter_mut() {
+        sprite.custom_size = Some(new_size.as_vec2());
+    }
+
+    let old_buffers = std::mem::replace(
+        &mut buffers.cell_buffers,
+        cell::create_cell_buffers(&device, new_size.x, new_size.y),
+    );
+
+    pending_copy.0 = Some(CellCopy {
+        old_buffers,
+        old_size,
+        new_size,
+        offset: event.anchor.offset(old_size, new_size),
+    });
+}
+
+/// Writes the new size and copies the rows of the old grid that overlap the new one, before
+/// this frame's simulation passes run. The size is written here rather than in the main
+/// world because the render world may still be running the previous frame with the old
+/// buffers, which would read them with the wrong size.
+fn copy_resized_cells(
+    pending_copy: Res<PendingCellCopy>,
+    buffers: Res<CellularAutomataBuffers>,
+    current_cell_buffer: Res<CurrentCellBuffer>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    let Some(copy) = &pending_copy.0 else {
+        return;
+    };
+
+    queue.write_buffer(&buffers.size_buffer, 0, bytemuck::cast_slice(&[copy.new_size.x, copy.new_size.y]));
+
+    let cell_size = std::mem::size_of::<Cell>() as u64;
+    let source = &copy.old_buffers[current_cell_buffer.0];
+    let destination = &buffers.cell_buffers[current_cell_buffer.0];
+
+    // The columns of each old row that land inside the new grid
+    let first_column = (-copy.offset.x).max(0);
+    let last_column = (copy.new_size.x as i32 - copy.offset.x).min(copy.old_size.x as i32);
+    if first_column >= last_column {
+        return;
+    }
+    let row_size = (last_column - first_column) as u64 * cell_size;
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Resize cell copy encoder"),
+    });
+    for row in 0..copy.old_size.y as i32 {
+        let new_row = row + copy.offset.y;
+        if new_row < 0 || new_row >= copy.new_size.y as i32 {
+            continue;
+        }
+
+        let source_offset = (row as u64 * copy.old_size.x as u64 + first_column as u64) * cell_size;
+        let destination_offset = (new_row as u64 * copy.new_size.x as u64
+            + (first_column + copy.offset.x) as u64) * cell_size;
+        encoder.copy_buffer_to_buffer(source, source_offset, destination, destination_offset, row_size);
+    }
+    queue.submit([encoder.finish()]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANCHORS: [(ResizeAnchor, IVec2); 9] = [
+        (ResizeAnchor::TopLeft, IVec2::new(0, 0)),
+        (ResizeAnchor::Top, IVec2::new(1, 0)),
+        (ResizeAnchor::TopRight, IVec2::new(2, 0)),
+        (ResizeAnchor::Left, IVec2::new(0, 2)),
+        (ResizeAnchor::Centre, IVec2::new(1, 2)),
+        (ResizeAnchor::Right, IVec2::new(2, 2)),
+        (ResizeAnchor::BottomLeft, IVec2::new(0, 4)),
+        (ResizeAnchor::Bottom, IVec2::new(1, 4)),
+        (ResizeAnchor::BottomRight, IVec2::new(2, 4)),
+    ];
+
+    #[test]
+    fn growing_moves_the_old_grid_towards_the_anchor() {
+        for (anchor, offset) in ANCHORS {
+            assert_eq!(anchor.offset(UVec2::new(10, 10), UVec2::new(12, 14)), offset, "{:?}", anchor);
+        }
+    }
+
+    #[test]
+    fn shrinking_cuts_away_the_side_opposite_the_anchor() {
+        for (anchor, offset) in ANCHORS {
+            assert_eq!(anchor.offset(UVec2::new(12, 14), UVec2::new(10, 10)), -offset, "{:?}", anchor);
+        }
+    }
+
+    #[test]
+    fn keeping_the_size_keeps_the_grid_in_place() {
+        for (anchor, _) in ANCHORS {
+            assert_eq!(anchor.offset(UVec2::new(10, 10), UVec2::new(10, 10)), IVec2::ZERO, "{:?}", anchor);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::path::Path;
+use std::str::FromStr;
+use bevy::log::warn;
+use bevy::math::UVec2;
+use bevy::prelude::Resource;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::settings::WgpuLimits;
+use serde::Deserialize;
+use crate::cell::Cell;
+
+/// Settings are read from this file in the working directory, if it exists.
+const SETTINGS_FILE: &str = "settings.ron";
+
+/// Settings chosen when the game is launched.
+#[derive(Resource, Clone, ExtractResource, Deserialize)]
+#[serde(default)]
+pub struct SimulationSettings {
+    /// The size of the simulation in cells.
+    pub width: u32,
+    pub height: u32,
+    /// Seeds the random numbers used by the simulation, the same seed gives the same run.
+    pub seed: u32,
+    /// How many times a second the simulation steps, however fast the game is rendering.
+    pub tick_rate: f32,
+    /// How many edits can be undone, memory permitting.
+    pub undo_depth: usize,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            seed: rand::random(),
+            tick_rate: 60.0,
+            undo_depth: 32,
+        }
+    }
+}
+
+impl SimulationSettings {
+    /// Reads the settings file and then applies any overrides from the command line,
+    /// e.g. `--width 3840 --height 2160 --seed 1234 --tick-rate 30 --undo-depth 8`.
+    /// The size is kept within what the GPU with these `limits` can simulate.
+    pub fn load(limits: &WgpuLimits) -> Self {
+        Self::from_file(Path::new(SETTINGS_FILE))
+            .unwrap_or_default()
+            .with_args(std::env::args().skip(1))
+            .clamped(limits)
+    }
+
+    /// Overrides the settings with any given in `args`.
+    fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--width" => parse_arg(&arg, args.next(), &mut self.width),
+                "--height" => parse_arg(&arg, args.next(), &mut self.height),
+                "--seed" => parse_arg(&arg, args.next(), &mut self.seed),
+                "--tick-rate" => parse_arg(&arg, args.next(), &mut self.tick_rate),
+                "--undo-depth" => parse_arg(&arg, args.next(), &mut self.undo_depth),
+                _ => warn!("Ignoring unknown argument {arg}"),
+            }
+        }
+        self
+    }
+
+    fn clamped(mut self, limits: &WgpuLimits) -> Self {
+        let size = clamp_size(UVec2::new(self.width, self.height), limits);
+        self.width = size.x;
+        self.height = size.y;
+        self.tick_rate = self.tick_rate.max(1.0);
+        self
+    }
+
+    fn from_file(path: &Path) -> Option<Self> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        match ron::from_str(&contents) {
+            Ok(settings) => Some(settings),
+            Err(error) => {
+                warn!("Ignoring {}: {error}", path.display());
+                None
+            }
+        }
+    }
+}
+
+/// The nearest size to `size` that the GPU with these `limits` can simulate, logging if it
+/// isn't `size` itself. The image the simulation is drawn to must fit in a texture and
+/// each cell buffer must fit in a single storage buffer binding.
+pub fn clamp_size(size: UVec2, limits: &WgpuLimits) -> UVec2 {
+    let mut clamped = size.max(UVec2::ONE).min(UVec2::splat(limits.max_texture_dimension_2d));
+
+    // Shrink both sides by the same factor so the simulation keeps its shape
+    let max_cells = limits.max_storage_buffer_binding_size as u64 / std::mem::size_of::<Cell>() as u64;
+    let cells = clamped.x as u64 * clamped.y as u64;
+    if cells > max_cells {
+        let scale = (max_cells as f64 / cells as f64).sqrt();
+        clamped = (clamped.as_dvec2() * scale).floor().as_uvec2().max(UVec2::ONE);
+    }
+
+    if clamped != size {
+        warn!("A {}x{} simulation is too big or small for this GPU, using {}x{} instead", size.x, size.y, clamped.x, clamped.y);
+    }
+    clamped
+}
+
+fn parse_arg<T: FromStr>(name: &str, value: Option<String>, target: &mut T) {
+    match value.map(|value| value.parse()) {
+        Some(Ok(value)) => *target = value,
+        _ => warn!("{name} expects a number"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_texture_dimension_2d: u32, max_storage_buffer_binding_size: u32) -> WgpuLimits {
+        WgpuLimits { max_texture_dimension_2d, max_storage_buffer_binding_size, ..Default::default() }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn command_line_overrides_the_settings_file() {
+        let file: SimulationSettings = ron::from_str("(width: 640, height: 480, seed: 7, tick_rate: 30.0)").unwrap();
+        let settings = file.with_args(args(&["--width", "800", "--seed", "1234"]));
+
+        assert_eq!((settings.width, settings.height), (800, 480));
+        assert_eq!(settings.seed, 1234);
+        assert_eq!(settings.tick_rate, 30.0);
+        assert_eq!(settings.undo_depth, SimulationSettings::default().undo_depth);
+    }
+
+    #[test]
+    fn bad_arguments_keep_the_settings_file() {
+        let file: SimulationSettings = ron::from_str("(width: 640, height: 480)").unwrap();
+        let settings = file.with_args(args(&["--height", "tall", "--colour", "red", "--width"]));
+
+        assert_eq!((settings.width, settings.height), (640, 480));
+    }
+
+    #[test]
+    fn loaded_settings_are_clamped() {
+        let settings = SimulationSettings { tick_rate: 0.0, ..Default::default() }
+            .with_args(args(&["--width", "100000", "--height", "0"]))
+            .clamped(&limits(8192, 128 << 20));
+
+        assert_eq!((settings.width, settings.height), (8192, 1));
+        assert_eq!(settings.tick_rate, 1.0);
+    }
+
+    #[test]
+    fn sizes_that_fit_are_kept() {
+        let size = UVec2::new(1280, 720);
+        assert_eq!(clamp_size(size, &limits(8192, 128 << 20)), size);
+    }
+
+    #[test]
+    fn empty_sizes_grow_to_one_cell() {
+        assert_eq!(clamp_size(UVec2::ZERO, &limits(8192, 128 << 20)), UVec2::ONE);
+    }
+
+    #[test]
+    fn sides_are_clamped_to_the_texture_limit() {
+        assert_eq!(clamp_size(UVec2::new(10000, 100), &limits(4096, u32::MAX)), UVec2::new(4096, 100));
+    }
+
+    #[test]
+    fn cell_buffers_are_clamped_to_the_binding_limit() {
+        let cell_size = std::mem::size_of::<Cell>() as u32;
+        let size = clamp_size(UVec2::new(4000, 2000), &limits(8192, 1000 * 500 * cell_size));
+
+        assert!(size.x as u64 * size.y as u64 * cell_size as u64 <= 1000 * 500 * cell_size as u64);
+        assert_eq!(size, UVec2::new(1000, 500));
+    }
+}